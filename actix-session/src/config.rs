@@ -1,9 +1,18 @@
 //! Configuration options to tune the behaviour of [`SessionMiddleware`].
 
-use actix_web::cookie::{time::Duration, Key, SameSite};
-use derive_more::From;
+use std::{error::Error as StdError, marker::PhantomData, rc::Rc};
 
-use crate::{storage::SessionStore, SessionMiddleware};
+use actix_web::{
+    cookie::{time::Duration, Key, SameSite},
+    http::header::HeaderName,
+};
+use derive_more::{Display, From};
+
+use crate::{
+    events::SessionEventListener,
+    storage::{SessionState, SessionStore},
+    DefaultNamespace, SessionMiddleware,
+};
 
 /// Determines what type of session cookie should be used and how its lifecycle should be managed.
 ///
@@ -183,6 +192,32 @@ pub enum TtlExtensionPolicy {
     OnStateChanges,
 }
 
+/// Determines how [`SessionMiddleware`] handles concurrent updates of the same session state—e.g.
+/// two parallel requests that both modify the session.
+///
+/// Detecting concurrent updates requires support from the storage backend (see
+/// [`SessionStore::update_versioned`]): `InMemorySessionStore` and `RedisSessionStore` support it.
+/// With other stores, the last write always wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConflictPolicy {
+    /// The session state is overwritten: changes made by concurrent requests are lost.
+    #[default]
+    LastWriteWins,
+
+    /// The request is rejected with a `409 Conflict` response—changes made while handling it are
+    /// not persisted.
+    Reject,
+
+    /// The changes made while handling the request are applied on top of the latest session
+    /// state and persisted again.
+    ///
+    /// Changes are tracked at the key level: keys inserted, modified or removed while handling the
+    /// request override their latest value. If the session state keeps being modified
+    /// concurrently, the request is rejected with a `409 Conflict` after a few attempts.
+    RetryMerge,
+}
+
 /// Determines how to secure the content of the session cookie.
 ///
 /// Used by [`SessionMiddlewareBuilder::cookie_content_security`].
@@ -201,6 +236,266 @@ pub enum CookieContentSecurity {
     Signed,
 }
 
+/// Binds sessions to a fingerprint of the client that created them, to mitigate the replay of
+/// stolen session cookies.
+///
+/// The fingerprint is recorded in the session state when the session is created. Requests whose
+/// fingerprint does not match the recorded one are handled according to
+/// [`on_mismatch`](Self::on_mismatch). Sessions created before a fingerprint policy was configured
+/// are bound to the first client that modifies them.
+///
+/// Used by [`SessionMiddlewareBuilder::fingerprint_policy`].
+///
+/// # Examples
+/// ```
+/// use actix_session::config::{FingerprintMismatchAction, FingerprintPolicy};
+///
+/// // bind sessions to the client's User-Agent and to the /24 (IPv4) or /64 (IPv6) network
+/// // it connects from, rejecting mismatched requests
+/// FingerprintPolicy::default()
+///     .ip_prefix(24, 64)
+///     .on_mismatch(FingerprintMismatchAction::Reject);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FingerprintPolicy {
+    pub(crate) user_agent: bool,
+    pub(crate) ip_prefix: Option<(u8, u8)>,
+    pub(crate) on_mismatch: FingerprintMismatchAction,
+}
+
+impl FingerprintPolicy {
+    /// Include (a hash of) the `User-Agent` header in the fingerprint.
+    ///
+    /// Default is `true`.
+    pub fn user_agent(mut self, user_agent: bool) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Include the network prefix of the client IP address in the fingerprint—i.e. the first
+    /// `ipv4_prefix_len` bits of IPv4 addresses, or the first `ipv6_prefix_len` bits of IPv6
+    /// addresses. Prefix lengths are capped to the size of the respective addresses.
+    ///
+    /// The client IP address is determined using [`ConnectionInfo::realip_remote_addr`], which
+    /// trusts the `Forwarded` and `X-Forwarded-For` headers: only enable this option if your
+    /// application sits behind a proxy that sets them.
+    ///
+    /// Keep in mind that the IP address of legitimate users can change during a session (e.g.
+    /// when switching networks)—a short prefix is less likely to log them out.
+    ///
+    /// By default, the client IP address is not part of the fingerprint.
+    ///
+    /// [`ConnectionInfo::realip_remote_addr`]: actix_web::dev::ConnectionInfo::realip_remote_addr
+    pub fn ip_prefix(mut self, ipv4_prefix_len: u8, ipv6_prefix_len: u8) -> Self {
+        self.ip_prefix = Some((ipv4_prefix_len.min(32), ipv6_prefix_len.min(128)));
+        self
+    }
+
+    /// Determine what happens to requests whose fingerprint does not match the one recorded in
+    /// the session state.
+    ///
+    /// Defaults to [`FingerprintMismatchAction::Purge`].
+    pub fn on_mismatch(mut self, action: FingerprintMismatchAction) -> Self {
+        self.on_mismatch = action;
+        self
+    }
+}
+
+impl Default for FingerprintPolicy {
+    fn default() -> Self {
+        Self {
+            user_agent: true,
+            ip_prefix: None,
+            on_mismatch: FingerprintMismatchAction::Purge,
+        }
+    }
+}
+
+/// Determines how requests whose client fingerprint does not match the one recorded in the
+/// session state are handled.
+///
+/// Used by [`FingerprintPolicy::on_mismatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FingerprintMismatchAction {
+    /// The request is rejected with a `401 Unauthorized` response. The session is left untouched.
+    Reject,
+
+    /// The session is deleted, both client and server side. The request is processed as if it
+    /// did not carry a session cookie.
+    ///
+    /// This logs out the legitimate owner of the session, as well as the client replaying the
+    /// session cookie.
+    Purge,
+}
+
+/// A cookie name prefix, instructing browsers to only accept the session cookie if it meets
+/// additional requirements.
+///
+/// Prefixes protect the session cookie from being overwritten by an insecure origin or by another
+/// subdomain. Used by [`SessionMiddlewareBuilder::cookie_prefix`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CookiePrefix {
+    /// The `__Secure-` prefix: the cookie must have the `Secure` attribute.
+    Secure,
+
+    /// The `__Host-` prefix: the cookie must have the `Secure` attribute, its `Path` must be `/`
+    /// and it must not have a `Domain` attribute—it is only sent to the host which set it.
+    Host,
+}
+
+impl CookiePrefix {
+    /// Returns the prefix, as it appears at the start of the cookie name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CookiePrefix::Secure => "__Secure-",
+            CookiePrefix::Host => "__Host-",
+        }
+    }
+
+    /// Returns the prefix of the given cookie name, if any.
+    ///
+    /// Browsers match prefixes case-insensitively.
+    pub(crate) fn of(cookie_name: &str) -> Option<Self> {
+        [CookiePrefix::Host, CookiePrefix::Secure]
+            .iter()
+            .copied()
+            .find(|prefix| {
+                cookie_name
+                    .get(..prefix.as_str().len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix.as_str()))
+            })
+    }
+}
+
+/// Determines how the session key is exchanged with the client.
+///
+/// Used by [`SessionMiddlewareBuilder::session_transport`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SessionTransport {
+    /// The session key is stored in a cookie, configured via the `cookie_*` methods of
+    /// [`SessionMiddlewareBuilder`].
+    Cookie,
+
+    /// The session key is sent in a request header and returned in a response header—e.g. for
+    /// mobile clients or single-page applications.
+    Header(HeaderTransport),
+}
+
+impl From<HeaderTransport> for SessionTransport {
+    fn from(transport: HeaderTransport) -> Self {
+        SessionTransport::Header(transport)
+    }
+}
+
+/// A [session transport](SessionTransport) where the session key is exchanged via HTTP headers.
+///
+/// Clients send the session key in a request header (`X-Session-Id` by default). A new session
+/// key, issued when the session is created or renewed, is returned in a response header with the
+/// same name; the response header is set to an empty value when the session is purged. Clients
+/// are expected to store the session key and attach it to subsequent requests.
+///
+/// The session key is signed or encrypted according to
+/// [`SessionMiddlewareBuilder::cookie_content_security`], just like the content of a session
+/// cookie. The other cookie settings do not apply.
+///
+/// Due to its `Into<SessionTransport>` implementation, a `HeaderTransport` can be passed directly
+/// to [`SessionMiddlewareBuilder::session_transport()`].
+///
+/// # Examples
+/// ```
+/// use actix_web::{cookie::Key, http::header::{HeaderName, AUTHORIZATION}};
+/// use actix_session::{config::HeaderTransport, storage::CookieSessionStore, SessionMiddleware};
+///
+/// // clients send `Authorization: Session <session key>`, new session keys are returned in the
+/// // `X-Session-Id` response header
+/// let transport = HeaderTransport::default()
+///     .header_name(AUTHORIZATION)
+///     .scheme(Some("Session".to_owned()))
+///     .response_header_name(HeaderName::from_static("x-session-id"));
+///
+/// SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
+///     .session_transport(transport)
+///     .build();
+/// ```
+///
+/// Browsers only let JavaScript read the response headers listed in
+/// `Access-Control-Expose-Headers` for cross-origin requests—remember to expose the response
+/// header if your client is served from another origin.
+#[derive(Debug, Clone)]
+pub struct HeaderTransport {
+    pub(crate) header_name: HeaderName,
+    pub(crate) scheme: Option<String>,
+    pub(crate) response_header_name: Option<HeaderName>,
+}
+
+impl HeaderTransport {
+    /// Set the name of the request header carrying the session key.
+    ///
+    /// Default is `X-Session-Id`.
+    pub fn header_name(mut self, header_name: HeaderName) -> Self {
+        self.header_name = header_name;
+        self
+    }
+
+    /// Set the authentication scheme expected to precede the session key in the request header,
+    /// e.g. `Session` for `Authorization: Session <session key>`. Schemes are matched
+    /// case-insensitively.
+    ///
+    /// The scheme is not included in the response header.
+    ///
+    /// By default, the request header carries the bare session key.
+    pub fn scheme(mut self, scheme: Option<String>) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Set the name of the response header carrying new session keys.
+    ///
+    /// By default, it is the same as the [request header name](Self::header_name).
+    pub fn response_header_name(mut self, header_name: HeaderName) -> Self {
+        self.response_header_name = Some(header_name);
+        self
+    }
+
+    /// Extracts the (still signed or encrypted) session key from the value of the request header.
+    pub(crate) fn parse<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let value = value.trim();
+
+        let session_key = match &self.scheme {
+            Some(scheme) => {
+                let (request_scheme, session_key) = value.split_once(' ')?;
+                if !request_scheme.eq_ignore_ascii_case(scheme) {
+                    return None;
+                }
+                session_key.trim_start()
+            }
+            None => value,
+        };
+
+        Some(session_key).filter(|session_key| !session_key.is_empty())
+    }
+
+    /// The name of the response header carrying new session keys.
+    pub(crate) fn outgoing_header_name(&self) -> &HeaderName {
+        self.response_header_name
+            .as_ref()
+            .unwrap_or(&self.header_name)
+    }
+}
+
+impl Default for HeaderTransport {
+    fn default() -> Self {
+        Self {
+            header_name: HeaderName::from_static("x-session-id"),
+            scheme: None,
+            response_header_name: None,
+        }
+    }
+}
+
 pub(crate) const fn default_ttl() -> Duration {
     Duration::days(1)
 }
@@ -211,16 +506,67 @@ pub(crate) const fn default_ttl_extension_policy() -> TtlExtensionPolicy {
 
 /// A fluent, customized [`SessionMiddleware`] builder.
 #[must_use]
-pub struct SessionMiddlewareBuilder<Store: SessionStore> {
+pub struct SessionMiddlewareBuilder<Store: SessionStore, N = DefaultNamespace> {
     storage_backend: Store,
     configuration: Configuration,
+    cookie_prefix: Option<CookiePrefix>,
+    namespace: PhantomData<fn() -> N>,
 }
 
-impl<Store: SessionStore> SessionMiddlewareBuilder<Store> {
+impl<Store: SessionStore, N: 'static> SessionMiddlewareBuilder<Store, N> {
     pub(crate) fn new(store: Store, configuration: Configuration) -> Self {
         Self {
             storage_backend: store,
             configuration,
+            cookie_prefix: None,
+            namespace: PhantomData,
+        }
+    }
+
+    /// Set the namespace of the sessions managed by the middleware—request handlers extract them
+    /// as `Session<M>`.
+    ///
+    /// Namespaces let several [`SessionMiddleware`] instances coexist within the same application,
+    /// e.g. on nested scopes: each instance manages the sessions of its namespace, with its own
+    /// cookie name, lifecycle and storage backend. Make sure that the session cookies (or
+    /// [headers](Self::session_transport)) of different namespaces have different names.
+    ///
+    /// Default is [`DefaultNamespace`], the namespace of [`Session`](crate::Session).
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{cookie::Key, web, App, HttpResponse};
+    /// use actix_session::{storage::CookieSessionStore, Session, SessionMiddleware};
+    ///
+    /// /// Marker for the sessions of the back-office.
+    /// struct Admin;
+    ///
+    /// async fn dashboard(admin_session: Session<Admin>) -> actix_web::Result<HttpResponse> {
+    ///     let admin_id = admin_session.get::<String>("admin_id")?;
+    ///     Ok(HttpResponse::Ok().body(format!("{:?}", admin_id)))
+    /// }
+    ///
+    /// let key = Key::generate();
+    /// let app = App::new()
+    ///     .service(
+    ///         web::scope("/admin")
+    ///             .wrap(
+    ///                 SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
+    ///                     .namespace::<Admin>()
+    ///                     .cookie_name("admin-id".to_owned())
+    ///                     .cookie_path("/admin".to_owned())
+    ///                     .build(),
+    ///             )
+    ///             .route("/dashboard", web::get().to(dashboard)),
+    ///     )
+    ///     .wrap(SessionMiddleware::new(CookieSessionStore::default(), key));
+    /// ```
+    pub fn namespace<M: 'static>(self) -> SessionMiddlewareBuilder<Store, M> {
+        SessionMiddlewareBuilder {
+            storage_backend: self.storage_backend,
+            configuration: self.configuration,
+            cookie_prefix: self.cookie_prefix,
+            namespace: PhantomData,
         }
     }
 
@@ -286,6 +632,16 @@ impl<Store: SessionStore> SessionMiddlewareBuilder<Store> {
         self
     }
 
+    /// Determines how the session key is exchanged with the client: in a cookie or in HTTP
+    /// headers. Check out [`SessionTransport`]'s documentation for more details on the available
+    /// options.
+    ///
+    /// Default is [`SessionTransport::Cookie`].
+    pub fn session_transport<T: Into<SessionTransport>>(mut self, transport: T) -> Self {
+        self.configuration.transport = transport.into();
+        self
+    }
+
     /// Set the `SameSite` attribute for the cookie used to store the session ID.
     ///
     /// By default, the attribute is set to `Lax`.
@@ -313,6 +669,36 @@ impl<Store: SessionStore> SessionMiddlewareBuilder<Store> {
         self
     }
 
+    /// Prefix the name of the cookie used to store the session ID (e.g. `__Host-id`).
+    ///
+    /// Browsers reject prefixed cookies which do not meet the requirements of their prefix—see
+    /// [`CookiePrefix`]. [`build`](Self::build) panics ([`try_build`](Self::try_build) returns an
+    /// error) if the cookie configuration does not meet them, rather than letting sessions silently
+    /// fail to be established.
+    ///
+    /// A cookie name set via [`cookie_name`](Self::cookie_name) which already starts with a
+    /// prefix is validated in the same way.
+    ///
+    /// By default, the cookie name is not prefixed.
+    pub fn cookie_prefix(mut self, prefix: Option<CookiePrefix>) -> Self {
+        self.cookie_prefix = prefix;
+        self
+    }
+
+    /// Set the `Partitioned` attribute for the cookie used to store the session ID.
+    ///
+    /// Partitioned cookies (also known as CHIPS) are stored separately for each top-level site
+    /// embedding your application, e.g. in an `iframe`. They are the way forward for third-party
+    /// cookies, as browsers phase out unpartitioned ones. Partitioned cookies must be secure:
+    /// [`build`](Self::build) panics ([`try_build`](Self::try_build) returns an error) if
+    /// [`cookie_secure`](Self::cookie_secure) is disabled.
+    ///
+    /// Default is `false`.
+    pub fn cookie_partitioned(mut self, partitioned: bool) -> Self {
+        self.configuration.cookie.partitioned = partitioned;
+        self
+    }
+
     /// Choose how the session cookie content should be secured.
     ///
     /// - [`CookieContentSecurity::Private`] selects encrypted cookie content.
@@ -343,25 +729,254 @@ impl<Store: SessionStore> SessionMiddlewareBuilder<Store> {
         self
     }
 
+    /// Set the keys that are still accepted, alongside the primary one, to verify (or decrypt) the
+    /// session cookie.
+    ///
+    /// Use fallback keys to rotate the secret key without logging out your users: pass the new key
+    /// to [`SessionMiddleware::builder`] and the old one as a fallback key. Session cookies
+    /// verified using a fallback key are re-issued using the primary key—the old key can be
+    /// removed once all sessions created before the rotation have expired or have been re-issued.
+    ///
+    /// Fallback keys are tried in order, after the primary key. By default, there are none.
+    pub fn cookie_fallback_keys(mut self, keys: Vec<Key>) -> Self {
+        self.configuration.cookie.fallback_keys = keys;
+        self
+    }
+
+    /// Bind sessions to a fingerprint of the client that created them.
+    ///
+    /// See [`FingerprintPolicy`] for more details. By default, sessions are not bound to the
+    /// client.
+    pub fn fingerprint_policy(mut self, policy: FingerprintPolicy) -> Self {
+        self.configuration.fingerprint_policy = Some(policy);
+        self
+    }
+
+    /// Register a listener to be notified of session lifecycle events (creation, renewal, purge,
+    /// etc.).
+    ///
+    /// Listeners are invoked in the order they were registered. See the [`events`](crate::events)
+    /// module for more details.
+    pub fn event_listener<L: SessionEventListener + 'static>(mut self, listener: L) -> Self {
+        self.configuration.event_listeners.push(Rc::new(listener));
+        self
+    }
+
+    /// Defer loading the session state until it is needed.
+    ///
+    /// By default, the session state is fetched from the storage backend before the request is
+    /// handed over to your handlers. With lazy loading, it is only fetched when a [`Session`] is
+    /// extracted—handlers that do not use the session (e.g. static assets, health checks) do not
+    /// pay for a round-trip to the storage backend. The session state of requests that never
+    /// accessed their session is not persisted, nor is its time-to-live extended.
+    ///
+    /// Sessions retrieved via [`SessionExt`] must be loaded using [`Session::load`] before they
+    /// are read—[`Session::get`] returns an error otherwise. They can be modified before being
+    /// loaded: insertions, removals and clears are replayed over the loaded state.
+    ///
+    /// Default is `false`.
+    ///
+    /// [`Session`]: crate::Session
+    /// [`Session::get`]: crate::Session::get
+    /// [`Session::load`]: crate::Session::load
+    /// [`SessionExt`]: crate::SessionExt
+    pub fn lazy_session_loading(mut self, lazy_loading: bool) -> Self {
+        self.configuration.lazy_loading = lazy_loading;
+        self
+    }
+
+    /// Set the maximum lifetime of a session, regardless of activity.
+    ///
+    /// The creation time of the session is recorded in the session state, alongside the state
+    /// exposed to request handlers. Requests carrying a session older than `timeout` get their
+    /// session purged before they reach your handlers, as if they did not carry a session cookie.
+    /// Renewing a session does not reset its creation time.
+    ///
+    /// Unlike the time-to-live set via [`session_lifecycle`](Self::session_lifecycle), it cannot be
+    /// extended. Sessions created before an absolute timeout was configured are timed from the
+    /// next time their state is modified.
+    ///
+    /// By default, there is no absolute timeout.
+    pub fn absolute_timeout(mut self, timeout: Duration) -> Self {
+        self.configuration.session.absolute_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how long a session can go unused before it is invalidated.
+    ///
+    /// The time the session was last accessed is recorded in the session state, alongside the
+    /// state exposed to request handlers. Requests carrying a session that was last accessed more
+    /// than `timeout` ago get their session purged before they reach your handlers, as if they did
+    /// not carry a session cookie.
+    ///
+    /// Every request accessing the session refreshes the time of last access, which requires
+    /// updating the session state in the storage backend—even if it was not otherwise modified.
+    /// With [lazy loading](Self::lazy_session_loading), requests that do not load the session are
+    /// not considered as accesses.
+    ///
+    /// By default, there is no idle timeout.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.configuration.session.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Register a migration of the session state to the next schema version—e.g. to rename a
+    /// key or to change the shape of a value after a change to the types stored in the session.
+    ///
+    /// The schema version is recorded in the session state, alongside the state exposed to request
+    /// handlers. Sessions created before any migration was registered are at version `0`: the
+    /// first migration you register turns a version `0` state into a version `1` state, the second
+    /// one turns a version `1` state into a version `2` state, etc. Register migrations in order,
+    /// and never remove one—the current schema version is the number of registered migrations.
+    ///
+    /// Outdated session states are migrated when they are loaded, before they reach your
+    /// handlers, and persisted right away. Session states that cannot be migrated—a migration
+    /// returned an error, or the state was written by a newer schema version—are reset, as if the
+    /// request did not carry a session cookie.
+    ///
+    /// ```
+    /// use actix_web::cookie::Key;
+    /// use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    ///
+    /// SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[0; 64]))
+    ///     // version 0 -> 1: `user_id` is now stored as `user`
+    ///     .state_migration(|state| {
+    ///         if let Some(user_id) = state.remove("user_id") {
+    ///             state.insert("user".to_owned(), user_id);
+    ///         }
+    ///         Ok(())
+    ///     })
+    ///     // version 1 -> 2: carts are now lists of items, not just item counts
+    ///     .state_migration(|state| match state.get("cart") {
+    ///         Some(cart) if !cart.is_u64() => anyhow::bail!("Unexpected cart: {}", cart),
+    ///         Some(_) => {
+    ///             state.insert("cart".to_owned(), serde_json::json!([]));
+    ///             Ok(())
+    ///         }
+    ///         None => Ok(()),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn state_migration<F>(mut self, migration: F) -> Self
+    where
+        F: Fn(&mut SessionState) -> Result<(), anyhow::Error> + 'static,
+    {
+        self.configuration
+            .session
+            .migrations
+            .push(Rc::new(migration));
+        self
+    }
+
+    /// Determine how concurrent updates of the same session state are handled.
+    ///
+    /// See [`ConflictPolicy`] for more details. Default is [`ConflictPolicy::LastWriteWins`].
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.configuration.conflict_policy = policy;
+        self
+    }
+
+    /// Set the maximum number of cookies the session key can be split across.
+    ///
+    /// Session keys that do not fit in a single cookie (i.e. they are longer than 4064 bytes) are
+    /// split into chunks, each stored in its own cookie: the session cookie name is used for the
+    /// first chunk, a numeric suffix is appended for the following ones (`id`, `id.1`, `id.2`,
+    /// etc.). Each chunk is signed or encrypted separately.
+    ///
+    /// This is only relevant when using `CookieSessionStore`—the session keys produced by other
+    /// storage backends are short, opaque identifiers. Requests fail with an
+    /// `Internal Server Error` if the session key needs more than `max_chunks` cookies.
+    ///
+    /// Keep in mind that browsers limit the number of cookies they store for a domain, as well as
+    /// their overall size.
+    ///
+    /// Default is `1`, i.e. the session key is never split.
+    ///
+    /// # Panics
+    /// Panics if `max_chunks` is `0`.
+    pub fn cookie_max_chunks(mut self, max_chunks: usize) -> Self {
+        assert!(
+            max_chunks > 0,
+            "The session key must be allowed to span at least one cookie"
+        );
+        self.configuration.cookie.max_chunks = max_chunks;
+        self
+    }
+
     /// Finalise the builder and return a [`SessionMiddleware`] instance.
+    ///
+    /// Use [`try_build`](Self::try_build) to handle an invalid cookie configuration without
+    /// panicking.
+    ///
+    /// # Panics
+    /// Panics if the cookie configuration does not meet the requirements of its
+    /// [prefix](Self::cookie_prefix) or of the [`Partitioned`](Self::cookie_partitioned)
+    /// attribute.
     #[must_use]
-    pub fn build(self) -> SessionMiddleware<Store> {
-        SessionMiddleware::from_parts(self.storage_backend, self.configuration)
+    pub fn build(self) -> SessionMiddleware<Store, N> {
+        match self.try_build() {
+            Ok(middleware) => middleware,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Finalise the builder and return a [`SessionMiddleware`] instance.
+    ///
+    /// It returns an error if the cookie configuration does not meet the requirements of its
+    /// [prefix](Self::cookie_prefix) or of the [`Partitioned`](Self::cookie_partitioned)
+    /// attribute.
+    pub fn try_build(mut self) -> Result<SessionMiddleware<Store, N>, InvalidCookieConfigError> {
+        if let Some(prefix) = self.cookie_prefix {
+            let cookie = &mut self.configuration.cookie;
+            if CookiePrefix::of(&cookie.name).is_none() {
+                cookie.name = format!("{}{}", prefix.as_str(), cookie.name);
+            }
+        }
+
+        self.configuration.cookie.validate()?;
+
+        Ok(SessionMiddleware::from_parts(
+            self.storage_backend,
+            self.configuration,
+        ))
+    }
+}
+
+/// Error returned by [`SessionMiddlewareBuilder::try_build`] when browsers would reject the
+/// session cookie.
+#[derive(Debug, Display, From)]
+#[display(fmt = "Invalid session cookie configuration: {}", _0)]
+pub struct InvalidCookieConfigError(anyhow::Error);
+
+impl StdError for InvalidCookieConfigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(self.0.as_ref())
     }
 }
 
 #[derive(Clone)]
 pub(crate) struct Configuration {
     pub(crate) cookie: CookieConfiguration,
+    pub(crate) transport: SessionTransport,
     pub(crate) session: SessionConfiguration,
     pub(crate) ttl_extension_policy: TtlExtensionPolicy,
+    pub(crate) fingerprint_policy: Option<FingerprintPolicy>,
+    pub(crate) event_listeners: Vec<Rc<dyn SessionEventListener>>,
+    pub(crate) lazy_loading: bool,
+    pub(crate) conflict_policy: ConflictPolicy,
 }
 
 #[derive(Clone)]
 pub(crate) struct SessionConfiguration {
     pub(crate) state_ttl: Duration,
+    pub(crate) absolute_timeout: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+    /// The n-th migration turns a version n session state into a version n + 1 session state.
+    pub(crate) migrations: Vec<Rc<StateMigration>>,
 }
 
+pub(crate) type StateMigration = dyn Fn(&mut SessionState) -> Result<(), anyhow::Error>;
+
 #[derive(Clone)]
 pub(crate) struct CookieConfiguration {
     pub(crate) secure: bool,
@@ -373,6 +988,31 @@ pub(crate) struct CookieConfiguration {
     pub(crate) max_age: Option<Duration>,
     pub(crate) content_security: CookieContentSecurity,
     pub(crate) key: Key,
+    pub(crate) fallback_keys: Vec<Key>,
+    pub(crate) max_chunks: usize,
+    pub(crate) partitioned: bool,
+}
+
+impl CookieConfiguration {
+    /// Checks that browsers will accept the session cookie.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.partitioned && !self.secure {
+            anyhow::bail!("partitioned cookies must be secure");
+        }
+
+        match CookiePrefix::of(&self.name) {
+            Some(prefix) if !self.secure => {
+                anyhow::bail!("`{}` cookies must be secure", prefix.as_str())
+            }
+            Some(CookiePrefix::Host) if self.path != "/" => {
+                anyhow::bail!("`__Host-` cookies must have `/` as path")
+            }
+            Some(CookiePrefix::Host) if self.domain.is_some() => {
+                anyhow::bail!("`__Host-` cookies must not have a domain")
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 pub(crate) fn default_configuration(key: Key) -> Configuration {
@@ -387,10 +1027,21 @@ pub(crate) fn default_configuration(key: Key) -> Configuration {
             max_age: None,
             content_security: CookieContentSecurity::Private,
             key,
+            fallback_keys: Vec::new(),
+            max_chunks: 1,
+            partitioned: false,
         },
+        transport: SessionTransport::Cookie,
         session: SessionConfiguration {
             state_ttl: default_ttl(),
+            absolute_timeout: None,
+            idle_timeout: None,
+            migrations: Vec::new(),
         },
         ttl_extension_policy: default_ttl_extension_policy(),
+        fingerprint_policy: None,
+        event_listeners: Vec::new(),
+        lazy_loading: false,
+        conflict_policy: ConflictPolicy::default(),
     }
 }
@@ -0,0 +1,630 @@
+//! Protection against cross-site request forgery (CSRF), tied to the session.
+//!
+//! `SameSite` cookies do not protect you from forged requests originating from other subdomains of
+//! your site (they are "same-site"). [`CsrfMiddleware`] requires requests using unsafe methods
+//! (i.e. anything but `GET`, `HEAD`, `OPTIONS` and `TRACE`) to carry a secret token, which is
+//! only known to pages served by your application. Requests lacking a valid token are rejected
+//! with a `403 Forbidden` response.
+//!
+//! Use the [`CsrfToken`] extractor to embed the token in your forms, as a hidden field, or in your
+//! pages, for JavaScript to send it back in a header.
+//!
+//! ```no_run
+//! use actix_web::{cookie::Key, web, App, HttpServer, HttpResponse, Responder};
+//! use actix_session::{csrf::{CsrfMiddleware, CsrfToken}, storage::CookieSessionStore, SessionMiddleware};
+//!
+//! async fn new_post(token: CsrfToken) -> impl Responder {
+//!     HttpResponse::Ok().body(format!(
+//!         r#"<form method="post" action="/posts">
+//!             <input type="hidden" name="{}" value="{}">
+//!             <textarea name="content"></textarea>
+//!         </form>"#,
+//!         token.form_field(),
+//!         token.as_str(),
+//!     ))
+//! }
+//!
+//! #[actix_web::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let secret_key = Key::generate();
+//!
+//!     HttpServer::new(move || {
+//!         App::new()
+//!             // `CsrfMiddleware` must be registered before `SessionMiddleware`, so that it is
+//!             // executed after it
+//!             .wrap(CsrfMiddleware::new())
+//!             .wrap(SessionMiddleware::new(CookieSessionStore::default(), secret_key.clone()))
+//!             .route("/posts/new", web::get().to(new_post))
+//!     })
+//!     .bind(("127.0.0.1", 8080))?
+//!     .run()
+//!     .await
+//! }
+//! ```
+//!
+//! # Token storage
+//! By default, the token is stored in the session state, under the reserved `$csrf` key. It is
+//! rotated when the session is [renewed](crate::Session::renew)—e.g. when a user logs in.
+//!
+//! Enable the double-submit cookie mode via [`CsrfMiddlewareBuilder::double_submit_cookie`] to
+//! have the token stored in a dedicated cookie instead, readable by JavaScript—which can send its
+//! value back in the CSRF header. This mode still relies on the session state: tokens are bound to
+//! the session, they carry a MAC, computed using the configured key, over a random per-session
+//! secret stored in the session state under the reserved `$csrf` key. A token obtained by another client (e.g. by an attacker visiting your
+//! site) does not match the secret of any other session, so planting it from another subdomain
+//! is useless. The secret, and therefore the token, is rotated when the session is renewed.
+//!
+//! # Namespaces
+//! [`CsrfMiddleware`] relies on the session of the [namespace](crate::Session#namespaces) it is
+//! bound to—the default one unless configured otherwise via
+//! [`CsrfMiddlewareBuilder::namespace`]. Extract the token as `CsrfToken<N>` for namespace `N`.
+//!
+//! # Submitting the token
+//! The token is read from the `X-CSRF-Token` header, or from the `csrf_token` field of URL-encoded
+//! forms (see [`CsrfMiddlewareBuilder::header_name`] and [`CsrfMiddlewareBuilder::form_field`]).
+//! Multipart forms are not inspected—send the token in the header instead.
+
+use std::{fmt, future::Future, marker::PhantomData, pin::Pin, rc::Rc};
+
+use actix_utils::future::{ready, Ready};
+use actix_web::{
+    body::MessageBody,
+    cookie::{Cookie, Key, SameSite},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header::HeaderName, Method},
+    web::Bytes,
+    FromRequest, HttpMessage as _, HttpRequest, HttpResponse,
+};
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+
+use crate::{
+    storage::utils::generate_session_key, DefaultNamespace, NamespacedSessionExt as _, Session,
+    SessionStatus,
+};
+
+/// The session state key holding the CSRF token.
+pub(crate) const CSRF_KEY: &str = "$csrf";
+
+/// Validates the CSRF token of requests using unsafe methods.
+///
+/// See the [module-level documentation](self) for more details.
+pub struct CsrfMiddleware<N = DefaultNamespace> {
+    configuration: Rc<CsrfConfiguration>,
+    namespace: PhantomData<fn() -> N>,
+}
+
+impl<N> Clone for CsrfMiddleware<N> {
+    fn clone(&self) -> Self {
+        Self {
+            configuration: Rc::clone(&self.configuration),
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl CsrfMiddleware {
+    /// Use [`CsrfMiddleware::new`] to protect your application using the default parameters:
+    /// tokens are stored in the session state and read from the `X-CSRF-Token` header or the
+    /// `csrf_token` form field.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// A fluent API to configure [`CsrfMiddleware`].
+    pub fn builder() -> CsrfMiddlewareBuilder {
+        CsrfMiddlewareBuilder {
+            configuration: CsrfConfiguration {
+                storage: TokenStorage::Session,
+                header_name: HeaderName::from_static("x-csrf-token"),
+                form_field: "csrf_token".to_owned(),
+                cookie: CsrfCookieConfiguration {
+                    name: "csrf_token".to_owned(),
+                    secure: true,
+                    path: "/".to_owned(),
+                    domain: None,
+                },
+            },
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl Default for CsrfMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fluent builder to construct a [`CsrfMiddleware`] instance with custom configuration
+/// parameters.
+#[must_use]
+pub struct CsrfMiddlewareBuilder<N = DefaultNamespace> {
+    configuration: CsrfConfiguration,
+    namespace: PhantomData<fn() -> N>,
+}
+
+impl<N: 'static> CsrfMiddlewareBuilder<N> {
+    /// Bind the middleware to the sessions of namespace `M`, managed by a
+    /// [`SessionMiddleware`](crate::SessionMiddleware) configured with the same
+    /// [namespace](crate::config::SessionMiddlewareBuilder::namespace).
+    ///
+    /// Tokens are then extracted as `CsrfToken<M>`. Default is [`DefaultNamespace`].
+    pub fn namespace<M: 'static>(self) -> CsrfMiddlewareBuilder<M> {
+        CsrfMiddlewareBuilder {
+            configuration: self.configuration,
+            namespace: PhantomData,
+        }
+    }
+
+    /// Set the name of the header carrying the CSRF token.
+    ///
+    /// Default is `X-CSRF-Token`.
+    pub fn header_name(mut self, header_name: HeaderName) -> Self {
+        self.configuration.header_name = header_name;
+        self
+    }
+
+    /// Set the name of the field carrying the CSRF token in URL-encoded forms.
+    ///
+    /// Default is `csrf_token`.
+    pub fn form_field(mut self, form_field: impl Into<String>) -> Self {
+        self.configuration.form_field = form_field.into();
+        self
+    }
+
+    /// Store the CSRF token in a cookie rather than in the session state.
+    ///
+    /// `key` is used to bind tokens to the session they were issued for—tokens planted by other
+    /// subdomains are rejected. Only a per-session secret is kept in the session state. Use a
+    /// different key than the one used by [`SessionMiddleware`](crate::SessionMiddleware).
+    ///
+    /// By default, the token is stored in the session state.
+    pub fn double_submit_cookie(mut self, key: Key) -> Self {
+        self.configuration.storage = TokenStorage::DoubleSubmitCookie(key);
+        self
+    }
+
+    /// Set the name of the cookie holding the CSRF token in double-submit cookie mode.
+    ///
+    /// Default is `csrf_token`.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.configuration.cookie.name = name.into();
+        self
+    }
+
+    /// Set the `Secure` attribute of the cookie holding the CSRF token in double-submit cookie
+    /// mode.
+    ///
+    /// Default is `true`.
+    pub fn cookie_secure(mut self, secure: bool) -> Self {
+        self.configuration.cookie.secure = secure;
+        self
+    }
+
+    /// Set the `Path` attribute of the cookie holding the CSRF token in double-submit cookie mode.
+    ///
+    /// Default is `/`.
+    pub fn cookie_path(mut self, path: impl Into<String>) -> Self {
+        self.configuration.cookie.path = path.into();
+        self
+    }
+
+    /// Set the `Domain` attribute of the cookie holding the CSRF token in double-submit cookie
+    /// mode.
+    ///
+    /// By default, the `Domain` attribute is left unspecified.
+    pub fn cookie_domain(mut self, domain: Option<String>) -> Self {
+        self.configuration.cookie.domain = domain;
+        self
+    }
+
+    /// Finalise the builder and return a [`CsrfMiddleware`] instance.
+    #[must_use]
+    pub fn build(self) -> CsrfMiddleware<N> {
+        CsrfMiddleware {
+            configuration: Rc::new(self.configuration),
+            namespace: PhantomData,
+        }
+    }
+}
+
+struct CsrfConfiguration {
+    storage: TokenStorage,
+    header_name: HeaderName,
+    form_field: String,
+    cookie: CsrfCookieConfiguration,
+}
+
+enum TokenStorage {
+    Session,
+    DoubleSubmitCookie(Key),
+}
+
+struct CsrfCookieConfiguration {
+    name: String,
+    secure: bool,
+    path: String,
+    domain: Option<String>,
+}
+
+/// The configuration of a [`CsrfMiddleware`] bound to namespace `N`, stored in the request
+/// extensions for [`CsrfToken<N>`] to retrieve it.
+struct CsrfSlot<N>(Rc<CsrfConfiguration>, PhantomData<fn() -> N>);
+
+impl CsrfConfiguration {
+    /// Returns the token carried by the cookie attached to the request, if any.
+    ///
+    /// The token is not authenticated: check that it is [bound](is_bound_to) to the session.
+    fn cookie_token(&self, req: &HttpRequest) -> Option<String> {
+        Some(req.cookie(&self.cookie.name)?.value().to_owned())
+    }
+
+    /// Creates a cookie holding a fresh token, bound to the session secret `secret` using `key`.
+    fn token_cookie(&self, key: &Key, secret: &str) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.cookie.name.clone(), bound_token(key, secret));
+        cookie.set_secure(self.cookie.secure);
+        cookie.set_http_only(false);
+        cookie.set_same_site(SameSite::Strict);
+        cookie.set_path(self.cookie.path.clone());
+        if let Some(domain) = &self.cookie.domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        cookie
+    }
+}
+
+/// A cookie holding a token issued while handling the request, to be attached to the response.
+struct IssuedCookie(Cookie<'static>);
+
+/// Generates a random CSRF token (or session secret, in double-submit cookie mode), with the same
+/// entropy as session keys.
+fn generate_token() -> String {
+    generate_session_key().into()
+}
+
+/// Computes the MAC binding `nonce` to the session secret `secret`.
+fn token_mac(key: &Key, secret: &str, nonce: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.signing()).expect("HMAC accepts keys of any length");
+    mac.update(b"actix-session csrf token\n");
+    mac.update(secret.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+/// Generates a fresh token bound to the session secret `secret`, formatted as `<nonce>.<MAC>`.
+fn bound_token(key: &Key, secret: &str) -> String {
+    let nonce = generate_token();
+    let mac = token_mac(key, secret, &nonce).finalize().into_bytes();
+    let mac = mac
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("{}.{}", nonce, mac)
+}
+
+/// Whether `token` was issued for the session whose secret is `secret`.
+fn is_bound_to(token: &str, key: &Key, secret: &str) -> bool {
+    let (nonce, mac) = match token.split_once('.') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let mac = match decode_hex(mac) {
+        Some(mac) => mac,
+        None => return false,
+    };
+
+    // `verify_slice` compares MACs in constant time
+    token_mac(key, secret, nonce).verify_slice(&mac).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Compares two tokens in constant time, to avoid leaking how much of a token was guessed right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Short-hand to create an `actix_web::Error` instance that will result in a `Forbidden` response
+/// while preserving the error root cause (e.g. in logs).
+fn e403<E: fmt::Debug + fmt::Display + 'static>(err: E) -> actix_web::Error {
+    actix_web::error::InternalError::from_response(err, HttpResponse::Forbidden().finish()).into()
+}
+
+impl<S, B, N> Transform<S, ServiceRequest> for CsrfMiddleware<N>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+    N: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = InnerCsrfMiddleware<S, N>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(InnerCsrfMiddleware {
+            service: Rc::new(service),
+            configuration: Rc::clone(&self.configuration),
+            namespace: PhantomData,
+        }))
+    }
+}
+
+#[doc(hidden)]
+#[non_exhaustive]
+pub struct InnerCsrfMiddleware<S, N = DefaultNamespace> {
+    service: Rc<S>,
+    configuration: Rc<CsrfConfiguration>,
+    namespace: PhantomData<fn() -> N>,
+}
+
+impl<S, B, N> Service<ServiceRequest> for InnerCsrfMiddleware<S, N>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    N: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let configuration = Rc::clone(&self.configuration);
+
+        Box::pin(async move {
+            req.extensions_mut()
+                .insert(CsrfSlot::<N>(Rc::clone(&configuration), PhantomData));
+
+            if !is_safe_method(req.method()) {
+                verify_token::<N>(&mut req, &configuration).await?;
+            }
+
+            let mut res = service.call(req).await?;
+
+            if let TokenStorage::DoubleSubmitCookie(key) = &configuration.storage {
+                let issued = res.request().extensions_mut().remove::<IssuedCookie>();
+                let session = res.request().get_namespaced_session::<N>();
+                // the token is rotated alongside the session key—tokens issued before the session
+                // was renewed are bound to its previous secret
+                let cookie = if session.status() == SessionStatus::Renewed {
+                    let secret = session_token(&session).await?;
+                    Some(configuration.token_cookie(key, &secret))
+                } else {
+                    issued.map(|IssuedCookie(cookie)| cookie)
+                };
+
+                if let Some(cookie) = cookie {
+                    res.response_mut().add_cookie(&cookie)?;
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+/// Methods that are not supposed to change the state of the application.
+fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Checks that the token submitted with the request matches the expected one.
+///
+/// In double-submit cookie mode, the token must also be bound to the session of the request.
+async fn verify_token<N: 'static>(
+    req: &mut ServiceRequest,
+    configuration: &CsrfConfiguration,
+) -> Result<(), actix_web::Error> {
+    let session = req.get_namespaced_session::<N>();
+    session.load().await?;
+    let session_token = session.get::<String>(CSRF_KEY).ok().flatten();
+
+    let expected = match &configuration.storage {
+        TokenStorage::Session => session_token,
+        TokenStorage::DoubleSubmitCookie(key) => match configuration.cookie_token(req.request()) {
+            Some(token)
+                if session_token.is_some_and(|secret| is_bound_to(&token, key, &secret)) =>
+            {
+                Some(token)
+            }
+            Some(_) => {
+                return Err(e403(anyhow::anyhow!(
+                    "The CSRF token was not issued for the session of the request"
+                )))
+            }
+            None => None,
+        },
+    };
+
+    let submitted = match req.headers().get(&configuration.header_name) {
+        Some(header) => header.to_str().ok().map(str::to_owned),
+        None => submitted_form_field(req, configuration).await?,
+    };
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted))
+            if constant_time_eq(expected.as_bytes(), submitted.as_bytes()) =>
+        {
+            Ok(())
+        }
+        (None, _) => Err(e403(anyhow::anyhow!(
+            "No CSRF token has been issued to the client"
+        ))),
+        (_, None) => Err(e403(anyhow::anyhow!(
+            "The request does not carry a CSRF token"
+        ))),
+        _ => Err(e403(anyhow::anyhow!("The CSRF token is invalid"))),
+    }
+}
+
+/// Reads the CSRF token from the body of URL-encoded forms.
+///
+/// The body is buffered, subject to the limits of [`PayloadConfig`](actix_web::web::PayloadConfig),
+/// and handed back to the request for handlers to read it.
+async fn submitted_form_field(
+    req: &mut ServiceRequest,
+    configuration: &CsrfConfiguration,
+) -> Result<Option<String>, actix_web::Error> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(None);
+    }
+
+    let body = req.extract::<Bytes>().await?;
+    req.set_payload(Payload::from(body.clone()));
+
+    let fields: Vec<(String, String)> = serde_urlencoded::from_bytes(&body).unwrap_or_default();
+
+    Ok(fields
+        .into_iter()
+        .find(|(name, _)| *name == configuration.form_field)
+        .map(|(_, value)| value))
+}
+
+/// The CSRF token to embed in pages served to the client.
+///
+/// A token is issued if the client does not have one yet. See the
+/// [module-level documentation](self) for an example.
+///
+/// `CsrfToken<N>` is the token of the [`CsrfMiddleware`] bound to namespace `N`. Extracting it
+/// fails with an `Internal Server Error` if no such [`CsrfMiddleware`] has been registered.
+pub struct CsrfToken<N = DefaultNamespace> {
+    token: String,
+    form_field: String,
+    header_name: HeaderName,
+    namespace: PhantomData<fn() -> N>,
+}
+
+impl<N> fmt::Debug for CsrfToken<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CsrfToken")
+            .field("token", &self.token)
+            .field("form_field", &self.form_field)
+            .field("header_name", &self.header_name)
+            .finish()
+    }
+}
+
+impl<N> Clone for CsrfToken<N> {
+    fn clone(&self) -> Self {
+        Self {
+            token: self.token.clone(),
+            form_field: self.form_field.clone(),
+            header_name: self.header_name.clone(),
+            namespace: PhantomData,
+        }
+    }
+}
+
+impl<N> CsrfToken<N> {
+    /// Returns the token.
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns the name of the form field expected to carry the token.
+    pub fn form_field(&self) -> &str {
+        &self.form_field
+    }
+
+    /// Returns the name of the header expected to carry the token.
+    pub fn header_name(&self) -> &HeaderName {
+        &self.header_name
+    }
+}
+
+impl<N> fmt::Display for CsrfToken<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.token)
+    }
+}
+
+impl<N: 'static> FromRequest for CsrfToken<N> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<CsrfToken<N>, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let configuration = req
+                .extensions()
+                .get::<CsrfSlot<N>>()
+                .map(|CsrfSlot(configuration, _)| Rc::clone(configuration))
+                .ok_or_else(|| {
+                    actix_web::error::ErrorInternalServerError(
+                        "`CsrfMiddleware` must be registered to extract `CsrfToken`s",
+                    )
+                })?;
+
+            let session = req.get_namespaced_session::<N>();
+            let token = match &configuration.storage {
+                TokenStorage::Session => session_token(&session).await?,
+                TokenStorage::DoubleSubmitCookie(key) => {
+                    let secret = session_token(&session).await?;
+                    let issued = req
+                        .extensions()
+                        .get::<IssuedCookie>()
+                        .map(|IssuedCookie(cookie)| cookie.value().to_owned());
+
+                    match issued
+                        .or_else(|| configuration.cookie_token(&req))
+                        .filter(|token| is_bound_to(token, key, &secret))
+                    {
+                        Some(token) => token,
+                        None => {
+                            let cookie = configuration.token_cookie(key, &secret);
+                            let token = cookie.value().to_owned();
+                            req.extensions_mut().insert(IssuedCookie(cookie));
+                            token
+                        }
+                    }
+                }
+            };
+
+            Ok(CsrfToken {
+                token,
+                form_field: configuration.form_field.clone(),
+                header_name: configuration.header_name.clone(),
+                namespace: PhantomData,
+            })
+        })
+    }
+}
+
+/// Returns the token stored in the session state, issuing one if there is none.
+///
+/// In double-submit cookie mode, it is the secret the tokens are bound to.
+async fn session_token<N: 'static>(session: &Session<N>) -> Result<String, actix_web::Error> {
+    session.load().await?;
+
+    if let Some(token) = session.get::<String>(CSRF_KEY)? {
+        return Ok(token);
+    }
+
+    let token = generate_token();
+    session.insert_entry(CSRF_KEY.to_owned(), &token)?;
+
+    Ok(token)
+}
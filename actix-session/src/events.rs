@@ -0,0 +1,169 @@
+//! Hooks to observe the lifecycle of sessions managed by [`SessionMiddleware`].
+//!
+//! Register a [`SessionEventListener`] via [`SessionMiddlewareBuilder::event_listener`] to emit
+//! audit logs or metrics when sessions are created, renewed, purged, etc.
+//!
+//! ```
+//! use actix_web::cookie::Key;
+//! use actix_session::{
+//!     events::{SessionEvent, SessionEventKind},
+//!     storage::CookieSessionStore,
+//!     SessionMiddleware,
+//! };
+//!
+//! let middleware = SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+//!     .event_listener(|event: &SessionEvent<'_>| {
+//!         if event.kind() == SessionEventKind::Purged {
+//!             tracing::info!(path = %event.request().uri, "User logged out");
+//!         }
+//!     })
+//!     .build();
+//! ```
+//!
+//! [`SessionMiddleware`]: crate::SessionMiddleware
+//! [`SessionMiddlewareBuilder::event_listener`]: crate::config::SessionMiddlewareBuilder::event_listener
+
+use std::{cell::RefCell, rc::Rc};
+
+use actix_web::{dev::RequestHead, http::StatusCode};
+
+use crate::storage::SessionKey;
+
+/// The interface to observe the lifecycle of sessions.
+///
+/// Listeners are invoked synchronously, once the response to the request that triggered the event
+/// has been produced or processing the request has failed—spawn a task if you need to perform
+/// I/O.
+///
+/// It is implemented for all closures that take a `&SessionEvent<'_>` as input.
+pub trait SessionEventListener {
+    /// Handle a session lifecycle event.
+    fn on_event(&self, event: &SessionEvent<'_>);
+}
+
+impl<F> SessionEventListener for F
+where
+    F: Fn(&SessionEvent<'_>),
+{
+    fn on_event(&self, event: &SessionEvent<'_>) {
+        self(event)
+    }
+}
+
+/// The kind of transition a session went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEventKind {
+    /// A new session has been created and its state persisted.
+    Created,
+
+    /// The state of an existing session has been updated.
+    Updated,
+
+    /// The session key has been renewed.
+    Renewed,
+
+    /// The session has been removed, both client and server side.
+    Purged,
+
+    /// The time-to-live of the session has been extended, without changes to its state.
+    TtlExtended,
+
+    /// The request carried a valid session key, but the corresponding session state could not be
+    /// found (e.g. it expired).
+    Expired,
+
+    /// The request carried an invalid session: its session cookie failed cryptographic checks,
+    /// its session state could not be deserialized or its client fingerprint did not match.
+    Invalid,
+}
+
+/// A session lifecycle event, passed to [`SessionEventListener`]s.
+#[derive(Debug)]
+pub struct SessionEvent<'a> {
+    kind: SessionEventKind,
+    session_key: Option<&'a SessionKey>,
+    request: &'a RequestHead,
+    status: StatusCode,
+}
+
+impl<'a> SessionEvent<'a> {
+    /// The kind of transition the session went through.
+    pub fn kind(&self) -> SessionEventKind {
+        self.kind
+    }
+
+    /// The key of the session the event refers to.
+    ///
+    /// For [`Created`](SessionEventKind::Created), [`Updated`](SessionEventKind::Updated) and
+    /// [`Renewed`](SessionEventKind::Renewed) events, it is the session key sent back to the
+    /// client. It is `None` for [`Invalid`](SessionEventKind::Invalid) events triggered by a
+    /// session cookie failing cryptographic checks.
+    ///
+    /// Session keys are bearer credentials (and, when using `CookieSessionStore`, they embed the
+    /// whole session state): avoid logging them verbatim.
+    pub fn session_key(&self) -> Option<&'a SessionKey> {
+        self.session_key
+    }
+
+    /// The head of the request that triggered the event.
+    pub fn request(&self) -> &'a RequestHead {
+        self.request
+    }
+
+    /// The status code of the response to the request that triggered the event—or of the error
+    /// response, if processing the request failed (e.g. the session state could not be
+    /// persisted).
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// Collects the events triggered while processing a request, to dispatch them once the response
+/// is ready.
+///
+/// Clones share the same recorded events.
+#[derive(Clone)]
+pub(crate) struct SessionEvents {
+    listeners: Rc<[Rc<dyn SessionEventListener>]>,
+    pending: Rc<RefCell<Vec<PendingEvent>>>,
+}
+
+type PendingEvent = (SessionEventKind, Option<SessionKey>);
+
+impl SessionEvents {
+    pub(crate) fn new(listeners: &[Rc<dyn SessionEventListener>]) -> Self {
+        Self {
+            listeners: listeners.into(),
+            pending: Rc::default(),
+        }
+    }
+
+    /// Whether any listeners have been registered.
+    pub(crate) fn has_listeners(&self) -> bool {
+        !self.listeners.is_empty()
+    }
+
+    /// Records an event. It is a no-op if no listeners have been registered.
+    pub(crate) fn record(&self, kind: SessionEventKind, session_key: Option<&SessionKey>) {
+        if self.has_listeners() {
+            self.pending.borrow_mut().push((kind, session_key.cloned()));
+        }
+    }
+
+    /// Dispatches the recorded events to all listeners.
+    pub(crate) fn dispatch(self, request: &RequestHead, status: StatusCode) {
+        for (kind, session_key) in self.pending.take() {
+            let event = SessionEvent {
+                kind,
+                session_key: session_key.as_ref(),
+                request,
+                status,
+            };
+
+            for listener in self.listeners.iter() {
+                listener.on_event(&event);
+            }
+        }
+    }
+}
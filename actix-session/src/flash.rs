@@ -0,0 +1,264 @@
+//! One-time messages carried over to the next request (e.g. "Your profile has been updated"),
+//! commonly used to report the outcome of a form submission after redirecting.
+//!
+//! Add a message using [`Session::flash`] and read it on a later request using the
+//! [`FlashMessages`] extractor. Messages are removed from the session as soon as they have been
+//! extracted—they are read exactly once.
+//!
+//! ```
+//! use actix_web::{http::header, HttpResponse, Responder};
+//! use actix_session::{
+//!     flash::{FlashMessages, Level},
+//!     Session,
+//! };
+//!
+//! async fn update_profile(session: Session) -> actix_web::Result<impl Responder> {
+//!     // [...]
+//!     session.flash(Level::Success, "Your profile has been updated")?;
+//!
+//!     Ok(HttpResponse::SeeOther()
+//!         .insert_header((header::LOCATION, "/profile"))
+//!         .finish())
+//! }
+//!
+//! async fn profile(messages: FlashMessages) -> impl Responder {
+//!     messages
+//!         .iter()
+//!         .map(|message| format!("[{}] {}", message.level(), message.text().unwrap_or_default()))
+//!         .collect::<Vec<_>>()
+//!         .join("\n")
+//! }
+//! # actix_web::web::to(update_profile);
+//! # actix_web::web::to(profile);
+//! ```
+//!
+//! Messages are stored in the session state, under the reserved `$flash` key. Any serializable
+//! value can be used as message, not just text—see [`FlashMessage::content_as`].
+
+use std::{fmt, future::Future, marker::PhantomData, pin::Pin, str::FromStr};
+
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+
+use crate::{DefaultNamespace, Session, SessionGetError};
+
+/// The session state key holding the flash messages that have not been read yet.
+pub(crate) const FLASH_KEY: &str = "$flash";
+
+/// The severity of a [`FlashMessage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// An informational message.
+    Info,
+
+    /// An action completed successfully.
+    Success,
+
+    /// Something might need the user's attention.
+    Warning,
+
+    /// An action failed.
+    Error,
+}
+
+impl Level {
+    /// Returns the lowercase name of the level (e.g. `success`)—handy as a CSS class name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Success => "success",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "info" => Ok(Level::Info),
+            "success" => Ok(Level::Success),
+            "warning" => Ok(Level::Warning),
+            "error" => Ok(Level::Error),
+            _ => Err(anyhow::anyhow!("`{}` is not a flash message level", level)),
+        }
+    }
+}
+
+/// A message added to the session via [`Session::flash`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashMessage {
+    level: Level,
+    content: serde_json::Value,
+}
+
+impl FlashMessage {
+    pub(crate) fn new(level: Level, content: serde_json::Value) -> Self {
+        Self { level, content }
+    }
+
+    /// Returns the severity of the message.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Returns the content of the message, as a JSON value.
+    pub fn content(&self) -> &serde_json::Value {
+        &self.content
+    }
+
+    /// Returns the content of the message, if it is text.
+    pub fn text(&self) -> Option<&str> {
+        self.content.as_str()
+    }
+
+    /// Deserializes the content of the message as `T`.
+    ///
+    /// It returns an error if the content was not flashed as a `T`.
+    pub fn content_as<T: DeserializeOwned>(&self) -> Result<T, SessionGetError> {
+        T::deserialize(&self.content)
+            .with_context(|| {
+                format!(
+                    "Failed to deserialize the flash message as a `{}` type",
+                    std::any::type_name::<T>()
+                )
+            })
+            .map_err(SessionGetError::from)
+    }
+
+    /// The representation of the message in the session state.
+    pub(crate) fn to_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "level": self.level.as_str(),
+            "content": self.content,
+        })
+    }
+
+    fn from_value(value: &serde_json::Value) -> Option<Self> {
+        let level = value.get("level")?.as_str()?.parse().ok()?;
+        let content = value.get("content")?.clone();
+
+        Some(Self { level, content })
+    }
+}
+
+/// The flash messages added to the session by previous requests, in the order they were added.
+///
+/// Extracting [`FlashMessages`] removes them from the session: they will not be returned again on
+/// subsequent requests. See the [module-level documentation](self) for an example.
+///
+/// `FlashMessages<N>` reads the flash messages of the `Session<N>` of the same
+/// [namespace](Session#namespaces).
+pub struct FlashMessages<N = DefaultNamespace>(Vec<FlashMessage>, PhantomData<fn() -> N>);
+
+impl<N> fmt::Debug for FlashMessages<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FlashMessages").field(&self.0).finish()
+    }
+}
+
+impl<N> Clone for FlashMessages<N> {
+    fn clone(&self) -> Self {
+        FlashMessages(self.0.clone(), PhantomData)
+    }
+}
+
+impl<N> Default for FlashMessages<N> {
+    fn default() -> Self {
+        FlashMessages(Vec::new(), PhantomData)
+    }
+}
+
+impl<N> FlashMessages<N> {
+    /// Returns the messages, in the order they were added.
+    pub fn iter(&self) -> std::slice::Iter<'_, FlashMessage> {
+        self.0.iter()
+    }
+
+    /// Returns the messages of the given severity.
+    pub fn with_level(&self, level: Level) -> impl Iterator<Item = &FlashMessage> {
+        self.0.iter().filter(move |message| message.level == level)
+    }
+
+    /// Returns the number of messages.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no messages.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<N: 'static> FlashMessages<N> {
+    /// Removes the flash messages from the session and returns them.
+    pub fn take(session: &Session<N>) -> FlashMessages<N> {
+        // `Session::remove` marks the session as changed, even if the key is missing
+        if !session.entries().contains_key(FLASH_KEY) {
+            return FlashMessages::default();
+        }
+
+        let messages = match session.remove(FLASH_KEY) {
+            Some(serde_json::Value::Array(messages)) => messages,
+            _ => Vec::new(),
+        };
+
+        FlashMessages(
+            messages
+                .iter()
+                .filter_map(|message| {
+                    let parsed = FlashMessage::from_value(message);
+                    if parsed.is_none() {
+                        tracing::warn!("Discarding a malformed flash message.");
+                    }
+                    parsed
+                })
+                .collect(),
+            PhantomData,
+        )
+    }
+}
+
+impl<N> IntoIterator for FlashMessages<N> {
+    type Item = FlashMessage;
+    type IntoIter = std::vec::IntoIter<FlashMessage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, N> IntoIterator for &'a FlashMessages<N> {
+    type Item = &'a FlashMessage;
+    type IntoIter = std::slice::Iter<'a, FlashMessage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Extractor implementation for [`FlashMessages`].
+///
+/// The session is loaded first, if [lazy loading] is enabled.
+///
+/// [lazy loading]: crate::config::SessionMiddlewareBuilder::lazy_session_loading
+impl<N: 'static> FromRequest for FlashMessages<N> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<FlashMessages<N>, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = Session::<N>::from_request(req, payload);
+
+        Box::pin(async move { Ok(FlashMessages::take(&session.await?)) })
+    }
+}
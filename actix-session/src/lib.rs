@@ -27,8 +27,8 @@
 //! against the active [`Session`].
 //!
 //! `actix-session` provides some built-in storage backends: ([`CookieSessionStore`],
-//! [`RedisSessionStore`], and [`RedisActorSessionStore`]) - you can create a custom storage backend
-//! by implementing the [`SessionStore`] trait.
+//! [`InMemorySessionStore`], [`RedisSessionStore`], and [`RedisActorSessionStore`]) - you can create
+//! a custom storage backend by implementing the [`SessionStore`] trait.
 //!
 //! Further reading on sessions:
 //! - [RFC6265](https://datatracker.ietf.org/doc/html/rfc6265);
@@ -99,6 +99,15 @@
 //!   actix-session = { version = "...", features = ["cookie-session"] }
 //!   ```
 //!
+//! - an in-process backend, [`InMemorySessionStore`], using the `in-memory-session` feature flag.
+//!   Handy for tests and single-instance deployments.
+//!
+//!   ```toml
+//!   [dependencies]
+//!   # ...
+//!   actix-session = { version = "...", features = ["in-memory-session"] }
+//!   ```
+//!
 //! - a Redis-based backend via [`actix-redis`](https://docs.rs/acitx-redis),
 //!   [`RedisActorSessionStore`], using the `redis-actor-session` feature flag.
 //!
@@ -126,12 +135,35 @@
 //!   actix-session = { version = "...", features = ["redis-rs-session", "redis-rs-tls-session"] }
 //!   ```
 //!
+//! - a SQL-based backend (SQLite or PostgreSQL) via [`sqlx`](https://docs.rs/sqlx),
+//!   [`SqlSessionStore`], using the `sql-session` feature flag.
+//!
+//!   ```toml
+//!   [dependencies]
+//!   # ...
+//!   actix-session = { version = "...", features = ["sql-session"] }
+//!   ```
+//!
 //! You can implement your own session storage backend using the [`SessionStore`] trait.
 //!
+//! Any storage backend can be wrapped with [`CachedSessionStore`], to serve hot sessions from an
+//! in-process cache rather than hitting the storage backend on every request.
+//!
+//! [`CookieSessionStore`], [`RedisSessionStore`], [`RedisActorSessionStore`] and
+//! [`SqlSessionStore`] encode the session state as JSON by default. Their builders accept a
+//! different [`SessionStateCodec`]—e.g. the more compact MessagePack (`msgpack-codec` feature flag)
+//! or CBOR (`cbor-codec` feature flag) codecs. Wrap any codec with [`EncryptedCodec`]
+//! (`encrypted-codec` feature flag) to encrypt the session state at rest.
+//!
 //! [`SessionStore`]: storage::SessionStore
+//! [`SessionStateCodec`]: storage::SessionStateCodec
 //! [`CookieSessionStore`]: storage::CookieSessionStore
+//! [`InMemorySessionStore`]: storage::InMemorySessionStore
 //! [`RedisSessionStore`]: storage::RedisSessionStore
 //! [`RedisActorSessionStore`]: storage::RedisActorSessionStore
+//! [`SqlSessionStore`]: storage::SqlSessionStore
+//! [`CachedSessionStore`]: storage::CachedSessionStore
+//! [`EncryptedCodec`]: storage::EncryptedCodec
 
 #![forbid(unsafe_code)]
 #![deny(rust_2018_idioms, nonstandard_style)]
@@ -141,20 +173,33 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 pub mod config;
+#[cfg(feature = "csrf")]
+#[cfg_attr(docsrs, doc(cfg(feature = "csrf")))]
+pub mod csrf;
+pub mod events;
+pub mod flash;
 mod middleware;
 mod session;
 mod session_ext;
 pub mod storage;
+pub mod test;
 
 pub use self::middleware::SessionMiddleware;
-pub use self::session::{Session, SessionGetError, SessionInsertError, SessionStatus};
-pub use self::session_ext::SessionExt;
+pub use self::session::{
+    DefaultNamespace, Session, SessionGetError, SessionInsertError, SessionLoadOutcome,
+    SessionStatus,
+};
+pub use self::session_ext::{NamespacedSessionExt, SessionExt};
 
+/// Test suites shared by the storage backends.
 #[cfg(test)]
 pub mod test_helpers {
     use actix_web::cookie::Key;
 
-    use crate::{config::CookieContentSecurity, storage::SessionStore};
+    use crate::{
+        config::CookieContentSecurity,
+        storage::{IndexedSessionStore, SessionKey, SessionStore},
+    };
 
     /// Generate a random cookie signing/encryption key.
     pub fn key() -> Key {
@@ -196,6 +241,114 @@ pub mod test_helpers {
         }
     }
 
+    /// A test suite for stores supporting optimistic concurrency control.
+    pub async fn optimistic_concurrency_test_suite<Store: SessionStore>(store: Store) {
+        use std::collections::HashMap;
+
+        use actix_web::cookie::time;
+        use serde_json::json;
+
+        use crate::storage::UpdateError;
+
+        let ttl = time::Duration::minutes(1);
+        let mut session_state = HashMap::new();
+        session_state.insert("cart".to_owned(), json!(["apple"]));
+        let session_key = store.save(session_state.clone(), &ttl).await.unwrap();
+
+        // two requests load the same session state...
+        let first = store.load_versioned(&session_key).await.unwrap().unwrap();
+        let second = store.load_versioned(&session_key).await.unwrap().unwrap();
+        assert_eq!(first.state, session_state);
+        let version = first.version.unwrap();
+        assert_eq!(second.version.as_ref(), Some(&version));
+
+        // ...the first one to update it wins...
+        session_state.insert("cart".to_owned(), json!(["apple", "pear"]));
+        let session_key = store
+            .update_versioned(session_key, session_state.clone(), &version, &ttl)
+            .await
+            .unwrap();
+        let latest = store.load_versioned(&session_key).await.unwrap().unwrap();
+        assert_eq!(latest.state, session_state);
+        assert_ne!(latest.version.as_ref(), Some(&version));
+
+        // ...while the second one is told about the conflict
+        let mut conflicting_state = HashMap::new();
+        conflicting_state.insert("cart".to_owned(), json!(["apple", "plum"]));
+        let err = store
+            .update_versioned(session_key.clone(), conflicting_state, &version, &ttl)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UpdateError::Conflict));
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(session_state));
+    }
+
+    /// A test suite for stores implementing [`IndexedSessionStore`], which must be configured to
+    /// read the subject of a session from the `user_id` key.
+    ///
+    /// [`IndexedSessionStore`]: crate::storage::IndexedSessionStore
+    pub async fn subject_index_test_suite<Store: IndexedSessionStore>(store: Store) {
+        use std::{collections::HashMap, time::SystemTime};
+
+        use actix_web::cookie::time;
+        use serde_json::json;
+
+        // the store might be shared with other test runs (e.g. Redis)
+        let nonce = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let subject = format!("user-{}", nonce);
+        let session_state = |user_id: serde_json::Value| {
+            let mut session_state = HashMap::new();
+            session_state.insert("user_id".to_owned(), user_id);
+            session_state
+        };
+        let sorted = |mut session_keys: Vec<SessionKey>| {
+            session_keys.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+            session_keys
+        };
+        let ttl = time::Duration::minutes(1);
+
+        let first = store
+            .save(session_state(json!(subject)), &ttl)
+            .await
+            .unwrap();
+        let second = store
+            .save(session_state(json!(subject)), &ttl)
+            .await
+            .unwrap();
+        let other = store.save(session_state(json!(nonce)), &ttl).await.unwrap();
+        store.save(HashMap::new(), &ttl).await.unwrap();
+
+        assert_eq!(
+            sorted(store.sessions_for(&subject).await.unwrap()),
+            sorted(vec![first.clone(), second.clone()])
+        );
+        assert_eq!(
+            store.sessions_for(&nonce.to_string()).await.unwrap(),
+            vec![other.clone()]
+        );
+
+        // sessions can be deleted, or change subject, during their lifetime
+        store.delete(&second).await.unwrap();
+        let first = store
+            .update(first, session_state(json!("someone-else")), &ttl)
+            .await
+            .unwrap();
+        assert!(store.sessions_for(&subject).await.unwrap().is_empty());
+
+        let third = store
+            .save(session_state(json!(subject)), &ttl)
+            .await
+            .unwrap();
+        assert_eq!(store.delete_all_for(&subject).await.unwrap(), 1);
+        assert!(store.sessions_for(&subject).await.unwrap().is_empty());
+        assert!(store.load(&third).await.unwrap().is_none());
+        assert!(store.load(&first).await.unwrap().is_some());
+        assert!(store.load(&other).await.unwrap().is_some());
+    }
+
     mod acceptance_tests {
         use actix_web::{
             cookie::time,
@@ -670,7 +823,7 @@ pub mod test_helpers {
                 .get::<i32>("counter")
                 .unwrap_or(Some(0))
                 .map_or(1, |inner| inner + 1);
-            session.insert("counter", &counter)?;
+            session.insert("counter", counter)?;
 
             Ok(HttpResponse::Ok().json(&IndexResponse { user_id, counter }))
         }
@@ -1,22 +1,42 @@
-use std::{collections::HashMap, convert::TryInto, fmt, future::Future, pin::Pin, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryInto,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    rc::Rc,
+};
 
 use actix_utils::future::{ready, Ready};
 use actix_web::{
     body::MessageBody,
-    cookie::{Cookie, CookieJar, Key},
+    cookie::{
+        time::{Duration, OffsetDateTime},
+        Cookie, CookieJar, Key,
+    },
     dev::{forward_ready, ResponseHead, Service, ServiceRequest, ServiceResponse, Transform},
-    http::header::{HeaderValue, SET_COOKIE},
-    HttpResponse,
+    http::header::{HeaderValue, SET_COOKIE, USER_AGENT},
+    HttpMessage as _, HttpResponse,
 };
 use anyhow::Context;
+use sha2::{Digest as _, Sha256};
 
 use crate::{
     config::{
-        self, Configuration, CookieConfiguration, CookieContentSecurity, SessionMiddlewareBuilder,
-        TtlExtensionPolicy,
+        self, Configuration, ConflictPolicy, CookieConfiguration, CookieContentSecurity,
+        CookiePrefix, FingerprintMismatchAction, FingerprintPolicy, HeaderTransport,
+        SessionMiddlewareBuilder, SessionTransport, StateMigration, TtlExtensionPolicy,
+    },
+    events::{SessionEventKind, SessionEvents},
+    session::{replay_changes, SessionStateLoader},
+    storage::{
+        LoadError, SessionKey, SessionState, SessionStore, SessionVersion, UpdateError,
+        VersionedSessionState, MAX_COOKIE_CONTENT_LENGTH,
     },
-    storage::{LoadError, SessionKey, SessionStore},
-    Session, SessionStatus,
+    DefaultNamespace, Session, SessionLoadOutcome, SessionStatus,
 };
 
 /// A middleware for session management in Actix Web applications.
@@ -26,7 +46,8 @@ use crate::{
 /// - Instructs the session storage backend to create/update/delete/retrieve the state attached to
 ///   a session according to its status and the operations that have been performed against it;
 /// - Set/remove a cookie, on the client side, to enable a user to be consistently associated with
-///   the same session across multiple HTTP requests.
+///   the same session across multiple HTTP requests. Clients which do not handle cookies can send
+///   the session key in a header instead—see [`SessionTransport`](crate::config::SessionTransport).
 ///
 /// Use [`SessionMiddleware::new`] to initialize the session framework using the default parameters.
 /// To create a new instance of [`SessionMiddleware`] you need to provide:
@@ -113,10 +134,27 @@ use crate::{
 ///         .await
 /// }
 /// ```
-#[derive(Clone)]
-pub struct SessionMiddleware<Store: SessionStore> {
+///
+/// # Namespaces
+/// A [`SessionMiddleware`] manages the [`Session`]s of a single namespace. Give each instance its
+/// own namespace, via [`SessionMiddlewareBuilder::namespace`], to use several of them within the
+/// same application—e.g. with different cookie names, lifecycles and storage backends for the
+/// back-office and for the public site. Request handlers extract `Session<N>` to access the
+/// session of namespace `N`.
+pub struct SessionMiddleware<Store: SessionStore, N = DefaultNamespace> {
     storage_backend: Rc<Store>,
     configuration: Rc<Configuration>,
+    namespace: PhantomData<fn() -> N>,
+}
+
+impl<Store: SessionStore, N> Clone for SessionMiddleware<Store, N> {
+    fn clone(&self) -> Self {
+        Self {
+            storage_backend: Rc::clone(&self.storage_backend),
+            configuration: Rc::clone(&self.configuration),
+            namespace: PhantomData,
+        }
+    }
 }
 
 impl<Store: SessionStore> SessionMiddleware<Store> {
@@ -140,25 +178,29 @@ impl<Store: SessionStore> SessionMiddleware<Store> {
     pub fn builder(store: Store, key: Key) -> SessionMiddlewareBuilder<Store> {
         SessionMiddlewareBuilder::new(store, config::default_configuration(key))
     }
+}
 
+impl<Store: SessionStore, N> SessionMiddleware<Store, N> {
     pub(crate) fn from_parts(store: Store, configuration: Configuration) -> Self {
         Self {
             storage_backend: Rc::new(store),
             configuration: Rc::new(configuration),
+            namespace: PhantomData,
         }
     }
 }
 
-impl<S, B, Store> Transform<S, ServiceRequest> for SessionMiddleware<Store>
+impl<S, B, Store, N> Transform<S, ServiceRequest> for SessionMiddleware<Store, N>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     B: MessageBody + 'static,
     Store: SessionStore + 'static,
+    N: 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = actix_web::Error;
-    type Transform = InnerSessionMiddleware<S, Store>;
+    type Transform = InnerSessionMiddleware<S, Store, N>;
     type InitError = ();
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
@@ -167,6 +209,7 @@ where
             service: Rc::new(service),
             configuration: Rc::clone(&self.configuration),
             storage_backend: Rc::clone(&self.storage_backend),
+            namespace: PhantomData,
         }))
     }
 }
@@ -187,19 +230,132 @@ fn e500<E: fmt::Debug + fmt::Display + 'static>(err: E) -> actix_web::Error {
     .into()
 }
 
+/// Short-hand to create an `actix_web::Error` instance that will result in an `Unauthorized`
+/// response while preserving the error root cause (e.g. in logs).
+fn e401<E: fmt::Debug + fmt::Display + 'static>(err: E) -> actix_web::Error {
+    actix_web::error::InternalError::from_response(err, HttpResponse::Unauthorized().finish())
+        .into()
+}
+
+/// Short-hand to create an `actix_web::Error` instance that will result in a `Conflict` response
+/// while preserving the error root cause (e.g. in logs).
+fn e409<E: fmt::Debug + fmt::Display + 'static>(err: E) -> actix_web::Error {
+    actix_web::error::InternalError::from_response(err, HttpResponse::Conflict().finish()).into()
+}
+
+/// The session state key used to record the fingerprint of the client that created the session.
+pub(crate) const FINGERPRINT_KEY: &str = "$fingerprint";
+
+/// The session state key used to record when the session was created, as a Unix timestamp.
+pub(crate) const CREATED_AT_KEY: &str = "$created_at";
+
+/// The session state key used to record when the session was last accessed, as a Unix timestamp.
+pub(crate) const LAST_ACCESS_KEY: &str = "$last_access";
+
+/// The session state key used to record the schema version of the session state.
+pub(crate) const SCHEMA_VERSION_KEY: &str = "$schema_version";
+
+/// Returns the current time as a Unix timestamp, in seconds.
+fn now_timestamp() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Whether more than `timeout` has elapsed since `timestamp`.
+///
+/// Sessions lacking a timestamp (e.g. created before the timeout was configured) never time out.
+fn has_timed_out(timestamp: Option<i64>, timeout: Option<Duration>, now: i64) -> bool {
+    match (timestamp, timeout) {
+        (Some(timestamp), Some(timeout)) => {
+            now.saturating_sub(timestamp) >= timeout.whole_seconds()
+        }
+        _ => false,
+    }
+}
+
+/// Migrates a session state from `version` to the current schema version, i.e. the number of
+/// registered migrations.
+///
+/// It returns whether any migration was applied.
+fn migrate_session_state(
+    session_state: &mut SessionState,
+    version: u64,
+    migrations: &[Rc<StateMigration>],
+) -> Result<bool, anyhow::Error> {
+    let current_version = migrations.len() as u64;
+    if version > current_version {
+        anyhow::bail!(
+            "The session state was written by a newer schema version ({}), the current one is {}",
+            version,
+            current_version
+        );
+    }
+
+    for (from_version, migration) in migrations.iter().enumerate().skip(version as usize) {
+        migration(session_state).with_context(|| {
+            format!(
+                "Failed to migrate the session state from schema version {}",
+                from_version
+            )
+        })?;
+    }
+
+    Ok(version < current_version)
+}
+
+/// Computes the fingerprint of the client that sent the request, according to `policy`.
+fn client_fingerprint(req: &ServiceRequest, policy: &FingerprintPolicy) -> String {
+    let mut hasher = Sha256::new();
+
+    if policy.user_agent {
+        hasher.update(b"user-agent:");
+        if let Some(user_agent) = req.headers().get(USER_AGENT) {
+            hasher.update(user_agent.as_bytes());
+        }
+        hasher.update(b"\n");
+    }
+
+    if let Some((ipv4_prefix_len, ipv6_prefix_len)) = policy.ip_prefix {
+        hasher.update(b"ip:");
+        let client_ip = req.connection_info().realip_remote_addr().and_then(|addr| {
+            addr.parse::<SocketAddr>()
+                .map(|addr| addr.ip())
+                .or_else(|_| addr.parse::<IpAddr>())
+                .ok()
+        });
+        match client_ip {
+            Some(IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(ipv4_prefix_len));
+                let network = u32::from(ip) & mask.unwrap_or(0);
+                hasher.update(format!("{}/{}", Ipv4Addr::from(network), ipv4_prefix_len));
+            }
+            Some(IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(ipv6_prefix_len));
+                let network = u128::from(ip) & mask.unwrap_or(0);
+                hasher.update(format!("{}/{}", Ipv6Addr::from(network), ipv6_prefix_len));
+            }
+            None => {}
+        }
+        hasher.update(b"\n");
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
 #[doc(hidden)]
 #[non_exhaustive]
-pub struct InnerSessionMiddleware<S, Store: SessionStore + 'static> {
+pub struct InnerSessionMiddleware<S, Store: SessionStore + 'static, N = DefaultNamespace> {
     service: Rc<S>,
     configuration: Rc<Configuration>,
     storage_backend: Rc<Store>,
+    namespace: PhantomData<fn() -> N>,
 }
 
-impl<S, B, Store> Service<ServiceRequest> for InnerSessionMiddleware<S, Store>
+impl<S, B, Store, N> Service<ServiceRequest> for InnerSessionMiddleware<S, Store, N>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     Store: SessionStore + 'static,
+    N: 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = actix_web::Error;
@@ -214,158 +370,757 @@ where
         let configuration = Rc::clone(&self.configuration);
 
         Box::pin(async move {
-            let session_key = extract_session_key(&req, &configuration.cookie);
-            let (session_key, session_state) =
-                load_session_state(session_key, storage_backend.as_ref()).await?;
-
-            Session::set_session(&mut req, session_state);
-
-            let mut res = service.call(req).await?;
-            let (status, session_state) = Session::get_changes(&mut res);
-
-            match session_key {
-                None => {
-                    // we do not create an entry in the session store if there is no state attached
-                    // to a fresh session
-                    if !session_state.is_empty() {
-                        let session_key = storage_backend
-                            .save(session_state, &configuration.session.state_ttl)
-                            .await
-                            .map_err(e500)?;
+            let session_key = extract_session_key::<N>(&req, &configuration);
+            let verified_with_fallback_key = matches!(session_key, Some((_, true)));
+            let session_key = session_key.map(|(session_key, _)| session_key);
+            let incoming_cookies = match &configuration.transport {
+                SessionTransport::Cookie => count_session_cookies(&req, &configuration.cookie),
+                SessionTransport::Header(transport) => {
+                    usize::from(session_header(&req, transport).is_some())
+                }
+            };
+            let events = SessionEvents::new(&configuration.event_listeners);
+
+            if session_key.is_none() && incoming_cookies > 0 {
+                events.record(SessionEventKind::Invalid, None);
+            }
+
+            // the fingerprint is kept out of the session state exposed to request handlers
+            let fingerprint = configuration
+                .fingerprint_policy
+                .as_ref()
+                .map(|policy| client_fingerprint(&req, policy));
+
+            let loaded_session = Rc::new(RefCell::new(None));
+            let loader: SessionStateLoader = Box::pin(load_session(
+                session_key.clone(),
+                fingerprint.clone(),
+                Rc::clone(&storage_backend),
+                Rc::clone(&configuration),
+                events.clone(),
+                Rc::clone(&loaded_session),
+            ));
+
+            if configuration.lazy_loading {
+                Session::<N>::set_loader(&mut req, loader);
+            } else {
+                match loader.await {
+                    Ok((session_state, load_outcome)) => {
+                        Session::<N>::set_session(&mut req.extensions_mut(), session_state);
+                        if let Some(load_outcome) = load_outcome {
+                            Session::<N>::set_load_outcome(&mut req.extensions_mut(), load_outcome);
+                        }
+                    }
+                    Err(err) => {
+                        events.dispatch(req.head(), err.as_response_error().status_code());
+                        return Err(err);
+                    }
+                }
+            }
 
-                        set_session_cookie(
-                            res.response_mut().head_mut(),
-                            session_key,
-                            &configuration.cookie,
-                        )
-                        .map_err(e500)?;
+            // the request is handed over to the request handler: its head is copied to dispatch
+            // the session events if the request handler fails
+            let request_head = events.has_listeners().then(|| req.head().clone());
+            let mut res = match service.call(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    if let Some(request_head) = request_head {
+                        events.dispatch(&request_head, err.as_response_error().status_code());
                     }
+                    return Err(err);
                 }
+            };
 
-                Some(session_key) => {
+            // persists the session; the events are dispatched whatever the outcome
+            let outcome = async {
+                let (status, mut session_state) = Session::<N>::get_changes(&mut res);
+
+                if let Some((loader, changes)) = Session::<N>::take_loader(&mut res) {
                     match status {
-                        SessionStatus::Changed => {
-                            let session_key = storage_backend
-                                .update(
-                                    session_key,
-                                    session_state,
-                                    &configuration.session.state_ttl,
-                                )
-                                .await
-                                .map_err(e500)?;
+                        // the session was never accessed, there is nothing to persist
+                        SessionStatus::Unchanged => return Ok(()),
 
-                            set_session_cookie(
-                                res.response_mut().head_mut(),
+                        // there is no need to load the session state to delete it
+                        SessionStatus::Purged => {
+                            *loaded_session.borrow_mut() = Some(LoadedSession {
                                 session_key,
-                                &configuration.cookie,
-                            )
-                            .map_err(e500)?;
+                                purged_on_load: false,
+                                migrated: false,
+                                created_at: None,
+                                version: None,
+                                original_state: None,
+                            });
                         }
 
-                        SessionStatus::Purged => {
-                            storage_backend.delete(&session_key).await.map_err(e500)?;
-
-                            delete_session_cookie(
-                                res.response_mut().head_mut(),
-                                &configuration.cookie,
-                            )
-                            .map_err(e500)?;
+                        // the session was modified without being loaded first
+                        SessionStatus::Changed | SessionStatus::Renewed => {
+                            let (mut loaded_state, _) = loader.await?;
+                            replay_changes(&mut loaded_state, changes);
+                            session_state = loaded_state;
                         }
+                    }
+                }
 
-                        SessionStatus::Renewed => {
-                            storage_backend.delete(&session_key).await.map_err(e500)?;
+                let loaded_session = loaded_session.borrow_mut().take();
+                let LoadedSession {
+                    session_key,
+                    purged_on_load,
+                    migrated,
+                    created_at,
+                    version,
+                    original_state,
+                } = match loaded_session {
+                    Some(loaded_session) => loaded_session,
+                    // loading the session state failed and the error was surfaced to the request
+                    // handler: we leave the session untouched
+                    None => return Ok(()),
+                };
 
+                let is_state_empty = session_state.is_empty();
+                if let Some(fingerprint) = fingerprint {
+                    session_state.insert(FINGERPRINT_KEY.to_owned(), fingerprint.into());
+                }
+
+                let session_config = &configuration.session;
+                let now = now_timestamp();
+                if session_config.absolute_timeout.is_some()
+                    || session_config.idle_timeout.is_some()
+                {
+                    session_state
+                        .insert(CREATED_AT_KEY.to_owned(), created_at.unwrap_or(now).into());
+                }
+                if session_config.idle_timeout.is_some() {
+                    session_state.insert(LAST_ACCESS_KEY.to_owned(), now.into());
+                }
+                if !session_config.migrations.is_empty() {
+                    session_state.insert(
+                        SCHEMA_VERSION_KEY.to_owned(),
+                        session_config.migrations.len().into(),
+                    );
+                }
+
+                // recording the last access is a change of the session state, and so is migrating it
+                let status = match status {
+                    SessionStatus::Unchanged
+                        if session_config.idle_timeout.is_some() || migrated =>
+                    {
+                        SessionStatus::Changed
+                    }
+                    status => status,
+                };
+
+                match session_key {
+                    None => {
+                        // we do not create an entry in the session store if there is no state attached
+                        // to a fresh session
+                        if !is_state_empty {
                             let session_key = storage_backend
                                 .save(session_state, &configuration.session.state_ttl)
                                 .await
                                 .map_err(e500)?;
+                            events.record(SessionEventKind::Created, Some(&session_key));
 
-                            set_session_cookie(
+                            set_session_key(
                                 res.response_mut().head_mut(),
                                 session_key,
-                                &configuration.cookie,
+                                &configuration,
+                                incoming_cookies,
+                            )
+                            .map_err(e500)?;
+                        } else if purged_on_load {
+                            delete_session_key(
+                                res.response_mut().head_mut(),
+                                &configuration,
+                                incoming_cookies,
                             )
                             .map_err(e500)?;
                         }
+                    }
+
+                    Some(session_key) => {
+                        match status {
+                            SessionStatus::Changed => {
+                                let session_key = update_session_state(
+                                    storage_backend.as_ref(),
+                                    &configuration,
+                                    session_key,
+                                    session_state,
+                                    version,
+                                    original_state,
+                                )
+                                .await?;
+                                events.record(SessionEventKind::Updated, Some(&session_key));
+
+                                set_session_key(
+                                    res.response_mut().head_mut(),
+                                    session_key,
+                                    &configuration,
+                                    incoming_cookies,
+                                )
+                                .map_err(e500)?;
+                            }
 
-                        SessionStatus::Unchanged => {
-                            if matches!(
-                                configuration.ttl_extension_policy,
-                                TtlExtensionPolicy::OnEveryRequest
-                            ) {
-                                storage_backend
-                                    .update_ttl(&session_key, &configuration.session.state_ttl)
+                            SessionStatus::Purged => {
+                                storage_backend.delete(&session_key).await.map_err(e500)?;
+                                events.record(SessionEventKind::Purged, Some(&session_key));
+
+                                delete_session_key(
+                                    res.response_mut().head_mut(),
+                                    &configuration,
+                                    incoming_cookies,
+                                )
+                                .map_err(e500)?;
+                            }
+
+                            SessionStatus::Renewed => {
+                                storage_backend.delete(&session_key).await.map_err(e500)?;
+
+                                let session_key = storage_backend
+                                    .save(session_state, &configuration.session.state_ttl)
                                     .await
                                     .map_err(e500)?;
+                                events.record(SessionEventKind::Renewed, Some(&session_key));
+
+                                set_session_key(
+                                    res.response_mut().head_mut(),
+                                    session_key,
+                                    &configuration,
+                                    incoming_cookies,
+                                )
+                                .map_err(e500)?;
+                            }
 
-                                if configuration.cookie.max_age.is_some() {
-                                    set_session_cookie(
+                            SessionStatus::Unchanged => {
+                                let extend_ttl = matches!(
+                                    configuration.ttl_extension_policy,
+                                    TtlExtensionPolicy::OnEveryRequest
+                                );
+
+                                if extend_ttl {
+                                    storage_backend
+                                        .update_ttl(&session_key, &configuration.session.state_ttl)
+                                        .await
+                                        .map_err(e500)?;
+                                    events
+                                        .record(SessionEventKind::TtlExtended, Some(&session_key));
+                                }
+
+                                // cookies verified using a fallback key are re-issued under the
+                                // primary key, so that the fallback key can eventually be retired
+                                if (extend_ttl && configuration.cookie.max_age.is_some())
+                                    || verified_with_fallback_key
+                                {
+                                    set_session_key(
                                         res.response_mut().head_mut(),
                                         session_key,
-                                        &configuration.cookie,
+                                        &configuration,
+                                        incoming_cookies,
                                     )
                                     .map_err(e500)?;
                                 }
                             }
-                        }
-                    };
+                        };
+                    }
                 }
+
+                Ok::<_, actix_web::Error>(())
             }
+            .await;
 
-            Ok(res)
+            match outcome {
+                Ok(()) => {
+                    events.dispatch(res.request().head(), res.status());
+                    Ok(res)
+                }
+                Err(err) => {
+                    events.dispatch(res.request().head(), err.as_response_error().status_code());
+                    Err(err)
+                }
+            }
         })
     }
 }
 
-/// Examines the session cookie attached to the incoming request, if there is one, and tries
-/// to extract the session key.
+/// Extracts the session key from the incoming request, according to the configured
+/// [`SessionTransport`].
 ///
-/// It returns `None` if there is no session cookie or if the session cookie is considered invalid
-/// (e.g., when failing a signature check).
-fn extract_session_key(req: &ServiceRequest, config: &CookieConfiguration) -> Option<SessionKey> {
-    let cookies = req.cookies().ok()?;
-    let session_cookie = cookies
-        .iter()
-        .find(|&cookie| cookie.name() == config.name)?;
-
-    let mut jar = CookieJar::new();
-    jar.add_original(session_cookie.clone());
+/// Alongside the session key, it returns whether it was verified using one of the fallback keys.
+fn extract_session_key<N: 'static>(
+    req: &ServiceRequest,
+    configuration: &Configuration,
+) -> Option<(SessionKey, bool)> {
+    match &configuration.transport {
+        SessionTransport::Cookie => {
+            extract_session_key_from_cookies::<N>(req, &configuration.cookie)
+        }
+        SessionTransport::Header(transport) => {
+            extract_session_key_from_header::<N>(req, transport, &configuration.cookie)
+        }
+    }
+}
 
-    let verification_result = match config.content_security {
-        CookieContentSecurity::Signed => jar.signed(&config.key).get(&config.name),
-        CookieContentSecurity::Private => jar.private(&config.key).get(&config.name),
+/// Examines the session cookie(s) attached to the incoming request, if there are any, and tries
+/// to extract the session key.
+///
+/// Session keys that were split across multiple cookies are reassembled. Alongside the session key,
+/// it returns whether any of the session cookies was verified using one of the fallback keys.
+///
+/// It returns `None` if there is no session cookie or if any of the session cookies is considered
+/// invalid (e.g., when failing a signature check). Invalid session cookies are recorded as
+/// [`SessionLoadOutcome::Tampered`] in the request extensions.
+fn extract_session_key_from_cookies<N: 'static>(
+    req: &ServiceRequest,
+    config: &CookieConfiguration,
+) -> Option<(SessionKey, bool)> {
+    // the cookies are collected upfront: they are borrowed from the request extensions, where the
+    // load outcome is recorded
+    let session_cookies = {
+        let cookies = req.cookies().ok()?;
+        (0..config.max_chunks)
+            .map_while(|chunk_index| {
+                let name = chunk_cookie_name(config, chunk_index);
+                cookies
+                    .iter()
+                    .find(|&cookie| cookie.name() == name)
+                    .cloned()
+            })
+            .collect::<Vec<_>>()
     };
+    if session_cookies.is_empty() {
+        return None;
+    }
+
+    let mut chunks = Vec::with_capacity(session_cookies.len());
+    let mut verified_with_fallback_key = false;
 
-    if verification_result.is_none() {
-        tracing::warn!(
-            "The session cookie attached to the incoming request failed to pass cryptographic \
-            checks (signature verification/decryption)."
+    for session_cookie in &session_cookies {
+        let verification_result = verify_cookie_with_any_key(session_cookie, config).map(
+            |(cookie, with_fallback_key)| {
+                verified_with_fallback_key |= with_fallback_key;
+                cookie
+            },
         );
+
+        if verification_result.is_none() {
+            tracing::warn!(
+                "The session cookie attached to the incoming request failed to pass cryptographic \
+                checks (signature verification/decryption)."
+            );
+            Session::<N>::set_load_outcome(&mut req.extensions_mut(), SessionLoadOutcome::Tampered);
+        }
+
+        chunks.push(verification_result?.value().to_owned());
     }
 
-    match verification_result?.value().to_owned().try_into() {
-        Ok(session_key) => Some(session_key),
+    match reassemble_session_key(chunks, config) {
+        Ok(session_key) => Some((session_key, verified_with_fallback_key)),
         Err(err) => {
             tracing::warn!(
                 error.message = %err,
                 error.cause_chain = ?err,
                 "Invalid session key, ignoring."
             );
+            Session::<N>::set_load_outcome(&mut req.extensions_mut(), SessionLoadOutcome::Tampered);
 
             None
         }
     }
 }
 
+/// Reassembles the session key from the (verified) content of the session cookies.
+///
+/// The chunks of a session key split across multiple cookies must carry the same nonce and chunk
+/// count, and be in order—see [`split_session_key`].
+fn reassemble_session_key(
+    mut chunks: Vec<String>,
+    config: &CookieConfiguration,
+) -> Result<SessionKey, anyhow::Error> {
+    if chunks.len() == 1 {
+        let value = chunks.pop().unwrap();
+        if parse_chunk(&value).is_some() {
+            anyhow::bail!("The session key is missing some of its chunks.");
+        }
+
+        return Ok(value.try_into()?);
+    }
+
+    let mut session_key = String::new();
+    let mut session_key_nonce = None;
+
+    for (chunk_index, chunk) in chunks.iter().enumerate() {
+        let (nonce, index, count, content) =
+            parse_chunk(chunk).context("The session key chunk has no valid header.")?;
+
+        if *session_key_nonce.get_or_insert(nonce) != nonce {
+            anyhow::bail!("The session key chunks belong to different session keys.");
+        }
+        if index != chunk_index || count != chunks.len() {
+            anyhow::bail!(
+                "Expected chunk {} out of {}, found chunk {} out of {}.",
+                chunk_index,
+                chunks.len(),
+                index,
+                count
+            );
+        }
+
+        session_key.push_str(content);
+    }
+
+    Ok(SessionKey::try_from_chunks(session_key, config.max_chunks)?)
+}
+
+/// Extracts the session key from the session header attached to the incoming request, if there
+/// is one.
+///
+/// Session keys failing cryptographic checks are recorded as [`SessionLoadOutcome::Tampered`] in
+/// the request extensions.
+fn extract_session_key_from_header<N: 'static>(
+    req: &ServiceRequest,
+    transport: &HeaderTransport,
+    config: &CookieConfiguration,
+) -> Option<(SessionKey, bool)> {
+    let value = session_header(req, transport)?;
+    let cookie = Cookie::new(transport.header_name.as_str().to_owned(), value);
+
+    match verify_cookie_with_any_key(&cookie, config) {
+        // the session key is authenticated, there is no size limit to enforce on headers
+        Some((cookie, with_fallback_key)) => Some((
+            SessionKey::new_unbounded(cookie.value().to_owned()),
+            with_fallback_key,
+        )),
+        None => {
+            tracing::warn!(
+                "The session key attached to the incoming request failed to pass cryptographic \
+                checks (signature verification/decryption)."
+            );
+            Session::<N>::set_load_outcome(&mut req.extensions_mut(), SessionLoadOutcome::Tampered);
+
+            None
+        }
+    }
+}
+
+/// Returns the (still signed or encrypted) session key carried by the session header, if any.
+fn session_header(req: &ServiceRequest, transport: &HeaderTransport) -> Option<String> {
+    let value = req.headers().get(&transport.header_name)?.to_str().ok()?;
+    transport.parse(value).map(str::to_owned)
+}
+
+/// Checks the signature of (or decrypts) a session cookie using the primary key, then the fallback
+/// keys. Alongside the verified cookie, it returns whether a fallback key was used.
+fn verify_cookie_with_any_key(
+    cookie: &Cookie<'static>,
+    config: &CookieConfiguration,
+) -> Option<(Cookie<'static>, bool)> {
+    if let Some(cookie) = verify_cookie(cookie, &config.key, config) {
+        return Some((cookie, false));
+    }
+
+    config
+        .fallback_keys
+        .iter()
+        .find_map(|key| verify_cookie(cookie, key, config))
+        .map(|cookie| (cookie, true))
+}
+
+/// Checks the signature of (or decrypts) a session cookie using `key`.
+fn verify_cookie(
+    cookie: &Cookie<'static>,
+    key: &Key,
+    config: &CookieConfiguration,
+) -> Option<Cookie<'static>> {
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie.clone());
+
+    match config.content_security {
+        CookieContentSecurity::Signed => jar.signed(key).get(cookie.name()),
+        CookieContentSecurity::Private => jar.private(key).get(cookie.name()),
+    }
+}
+
+/// Returns the number of session cookies attached to the incoming request—i.e. the number of
+/// chunks the session key was split into, including chunks we might not be able to reassemble.
+fn count_session_cookies(req: &ServiceRequest, config: &CookieConfiguration) -> usize {
+    let cookies = match req.cookies() {
+        Ok(cookies) => cookies,
+        Err(_) => return 0,
+    };
+
+    (0..config.max_chunks)
+        .rev()
+        .find(|&chunk_index| {
+            let name = chunk_cookie_name(config, chunk_index);
+            cookies.iter().any(|cookie| cookie.name() == name)
+        })
+        .map_or(0, |chunk_index| chunk_index + 1)
+}
+
+/// The name of the cookie holding the `chunk_index`-th chunk of the session key.
+fn chunk_cookie_name(config: &CookieConfiguration, chunk_index: usize) -> String {
+    if chunk_index == 0 {
+        config.name.clone()
+    } else {
+        format!("{}.{}", config.name, chunk_index)
+    }
+}
+
+/// Splits the session key into chunks that fit in a cookie, on UTF-8 character boundaries.
+///
+/// Session keys fitting in a single cookie are left untouched. Otherwise, every chunk starts with
+/// a `<nonce>.<chunk index>.<chunk count>.` header, where the nonce is drawn at random for each
+/// session key: chunks are signed (or encrypted) one by one, the header ties each of them to the
+/// others, so that chunks of different session keys cannot be mixed and matched.
+fn split_session_key(
+    session_key: &str,
+    config: &CookieConfiguration,
+) -> Result<Vec<String>, anyhow::Error> {
+    if session_key.len() <= MAX_COOKIE_CONTENT_LENGTH {
+        return Ok(vec![session_key.to_owned()]);
+    }
+
+    if config.max_chunks == 1 {
+        anyhow::bail!(
+            "The session key is bigger than 4064 bytes, the upper limit on cookie content."
+        );
+    }
+
+    // leave room for the longest chunk header
+    let max_chunk_length = MAX_COOKIE_CONTENT_LENGTH
+        - (CHUNK_NONCE_LENGTH + 2 * config.max_chunks.to_string().len() + 3);
+
+    let mut contents = Vec::new();
+    let mut rest = session_key;
+
+    while rest.len() > max_chunk_length {
+        let mut split_at = max_chunk_length;
+        while !rest.is_char_boundary(split_at) {
+            split_at -= 1;
+        }
+
+        let (content, tail) = rest.split_at(split_at);
+        contents.push(content);
+        rest = tail;
+    }
+    contents.push(rest);
+
+    if contents.len() > config.max_chunks {
+        anyhow::bail!(
+            "The session key needs {} cookies, more than the configured maximum of {}.",
+            contents.len(),
+            config.max_chunks
+        );
+    }
+
+    let nonce = generate_chunk_nonce();
+    let count = contents.len();
+
+    Ok(contents
+        .into_iter()
+        .enumerate()
+        .map(|(index, content)| format!("{}.{}.{}.{}", nonce, index, count, content))
+        .collect())
+}
+
+/// The length of the nonce shared by the chunks of a session key, in hex digits.
+const CHUNK_NONCE_LENGTH: usize = 32;
+
+/// Draws the nonce shared by the chunks of a session key.
+fn generate_chunk_nonce() -> String {
+    // the master key is drawn from a CSPRNG
+    Key::generate().master()[..CHUNK_NONCE_LENGTH / 2]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Splits a chunk of a session key into its nonce, index, the chunk count and its content.
+///
+/// It returns `None` if the chunk does not start with a header—see [`split_session_key`].
+fn parse_chunk(chunk: &str) -> Option<(&str, usize, usize, &str)> {
+    let mut parts = chunk.splitn(4, '.');
+
+    let nonce = parts.next()?;
+    if nonce.len() != CHUNK_NONCE_LENGTH || !nonce.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let index = parts.next()?.parse().ok()?;
+    let count = parts.next()?.parse().ok()?;
+    let content = parts.next()?;
+
+    Some((nonce, index, count, content))
+}
+
+/// The session key the session state was loaded from, if any, alongside whether the session was
+/// purged because of a mismatched client fingerprint or a timeout.
+struct LoadedSession {
+    session_key: Option<SessionKey>,
+    purged_on_load: bool,
+    /// Whether the session state was migrated to the current schema version.
+    migrated: bool,
+    /// When the session was created, if it was recorded in the session state.
+    created_at: Option<i64>,
+    /// The version of the loaded session state, if the store supports versioning.
+    version: Option<SessionVersion>,
+    /// The session state as loaded, kept to merge changes on conflicts.
+    original_state: Option<SessionState>,
+}
+
+/// Loads the session state attached to `session_key` and checks the client fingerprint and the
+/// timestamps recorded in it, if any.
+///
+/// The session key to be used when persisting the session state is recorded in `loaded_session`.
+/// Alongside the session state, it returns the outcome of the load if there was a session key.
+async fn load_session<Store: SessionStore>(
+    session_key: Option<SessionKey>,
+    fingerprint: Option<String>,
+    storage_backend: Rc<Store>,
+    configuration: Rc<Configuration>,
+    events: SessionEvents,
+    loaded_session: Rc<RefCell<Option<LoadedSession>>>,
+) -> Result<(SessionState, Option<SessionLoadOutcome>), actix_web::Error> {
+    let (
+        mut session_key,
+        VersionedSessionState {
+            state: mut session_state,
+            version,
+        },
+        mut load_outcome,
+    ) = load_session_state(session_key, storage_backend.as_ref(), &events).await?;
+
+    let mut purged_on_load = false;
+    if let (Some(policy), Some(fingerprint)) = (&configuration.fingerprint_policy, fingerprint) {
+        match session_state.remove(FINGERPRINT_KEY) {
+            Some(recorded) if recorded != fingerprint.as_str() => {
+                tracing::warn!(
+                    action = ?policy.on_mismatch,
+                    "The client fingerprint does not match the one recorded when the session was \
+                    created."
+                );
+                events.record(SessionEventKind::Invalid, session_key.as_ref());
+
+                if policy.on_mismatch == FingerprintMismatchAction::Reject {
+                    return Err(e401(anyhow::anyhow!(
+                        "The client fingerprint does not match the session"
+                    )));
+                }
+
+                if let Some(session_key) = session_key.take() {
+                    storage_backend.delete(&session_key).await.map_err(e500)?;
+                    events.record(SessionEventKind::Purged, Some(&session_key));
+                }
+                session_state.clear();
+                purged_on_load = true;
+                load_outcome = Some(SessionLoadOutcome::Tampered);
+            }
+            _ => {}
+        }
+    }
+
+    // the timestamps are kept out of the session state exposed to request handlers
+    let created_at = session_state
+        .remove(CREATED_AT_KEY)
+        .and_then(|created_at| created_at.as_i64());
+    let last_access = session_state
+        .remove(LAST_ACCESS_KEY)
+        .and_then(|last_access| last_access.as_i64());
+
+    let now = now_timestamp();
+    let session_config = &configuration.session;
+    let timed_out = has_timed_out(created_at, session_config.absolute_timeout, now)
+        || has_timed_out(last_access, session_config.idle_timeout, now);
+
+    if timed_out {
+        if let Some(session_key) = session_key.take() {
+            tracing::info!("The session has timed out, purging it.");
+            events.record(SessionEventKind::Expired, Some(&session_key));
+
+            storage_backend.delete(&session_key).await.map_err(e500)?;
+            events.record(SessionEventKind::Purged, Some(&session_key));
+
+            session_state.clear();
+            purged_on_load = true;
+            load_outcome = Some(SessionLoadOutcome::Expired);
+        }
+    }
+
+    // the schema version is kept out of the session state exposed to request handlers
+    let schema_version = session_state
+        .remove(SCHEMA_VERSION_KEY)
+        .and_then(|schema_version| schema_version.as_u64())
+        .unwrap_or(0);
+
+    let mut migrated = false;
+    if !session_state.is_empty() {
+        if let Some(key) = &session_key {
+            match migrate_session_state(
+                &mut session_state,
+                schema_version,
+                &session_config.migrations,
+            ) {
+                Ok(applied) => migrated = applied,
+                Err(err) => {
+                    tracing::warn!(
+                        error.message = %err,
+                        error.cause_chain = ?err,
+                        "The session state cannot be migrated to the current schema version, \
+                        resetting the session."
+                    );
+                    events.record(SessionEventKind::Invalid, Some(key));
+
+                    storage_backend.delete(key).await.map_err(e500)?;
+                    events.record(SessionEventKind::Purged, Some(key));
+
+                    session_key = None;
+                    session_state.clear();
+                    purged_on_load = true;
+                    load_outcome = Some(SessionLoadOutcome::Corrupted);
+                }
+            }
+        }
+    }
+
+    let original_state = match (configuration.conflict_policy, &version) {
+        (ConflictPolicy::RetryMerge, Some(_)) => Some(session_state.clone()),
+        _ => None,
+    };
+
+    *loaded_session.borrow_mut() = Some(LoadedSession {
+        created_at: if purged_on_load { None } else { created_at },
+        session_key,
+        purged_on_load,
+        migrated,
+        version,
+        original_state,
+    });
+
+    Ok((session_state, load_outcome))
+}
+
+/// Loads the session state attached to `session_key`, if any, alongside the outcome of the load.
 async fn load_session_state<Store: SessionStore>(
     session_key: Option<SessionKey>,
     storage_backend: &Store,
-) -> Result<(Option<SessionKey>, HashMap<String, String>), actix_web::Error> {
+    events: &SessionEvents,
+) -> Result<
+    (
+        Option<SessionKey>,
+        VersionedSessionState,
+        Option<SessionLoadOutcome>,
+    ),
+    actix_web::Error,
+> {
+    let empty_state = || VersionedSessionState {
+        state: HashMap::new(),
+        version: None,
+    };
+
     if let Some(session_key) = session_key {
-        match storage_backend.load(&session_key).await {
+        match storage_backend.load_versioned(&session_key).await {
             Ok(state) => {
                 if let Some(state) = state {
-                    Ok((Some(session_key), state))
+                    Ok((Some(session_key), state, Some(SessionLoadOutcome::Loaded)))
                 } else {
                     // We discard the existing session key given that the state attached to it can
                     // no longer be found (e.g. it expired or we suffered some data loss in the
@@ -377,8 +1132,9 @@ async fn load_session_state<Store: SessionStore>(
                         "No session state has been found for a valid session key, creating a new \
                         empty session."
                     );
+                    events.record(SessionEventKind::Expired, Some(&session_key));
 
-                    Ok((None, HashMap::new()))
+                    Ok((None, empty_state(), Some(SessionLoadOutcome::Expired)))
                 }
             }
 
@@ -389,51 +1145,225 @@ async fn load_session_state<Store: SessionStore>(
                         error.cause_chain = ?err,
                         "Invalid session state, creating a new empty session."
                     );
+                    events.record(SessionEventKind::Invalid, Some(&session_key));
 
-                    Ok((Some(session_key), HashMap::new()))
+                    Ok((
+                        Some(session_key),
+                        empty_state(),
+                        Some(SessionLoadOutcome::Corrupted),
+                    ))
                 }
 
                 LoadError::Other(err) => Err(e500(err)),
             },
         }
     } else {
-        Ok((None, HashMap::new()))
+        Ok((None, empty_state(), None))
     }
 }
 
-fn set_session_cookie(
-    response: &mut ResponseHead,
+/// The number of times the session state is persisted with [`ConflictPolicy::RetryMerge`] before
+/// giving up.
+const MAX_UPDATE_ATTEMPTS: usize = 3;
+
+/// Updates the session state, detecting concurrent updates according to the configured
+/// [`ConflictPolicy`].
+async fn update_session_state<Store: SessionStore>(
+    storage_backend: &Store,
+    configuration: &Configuration,
     session_key: SessionKey,
-    config: &CookieConfiguration,
-) -> Result<(), anyhow::Error> {
-    let value: String = session_key.into();
-    let mut cookie = Cookie::new(config.name.clone(), value);
+    session_state: SessionState,
+    version: Option<SessionVersion>,
+    original_state: Option<SessionState>,
+) -> Result<SessionKey, actix_web::Error> {
+    let ttl = &configuration.session.state_ttl;
+
+    let mut version = match (configuration.conflict_policy, version) {
+        (ConflictPolicy::LastWriteWins, _) | (_, None) => {
+            return storage_backend
+                .update(session_key, session_state, ttl)
+                .await
+                .map_err(e500);
+        }
+        (_, Some(version)) => version,
+    };
 
-    cookie.set_secure(config.secure);
-    cookie.set_http_only(config.http_only);
-    cookie.set_same_site(config.same_site);
-    cookie.set_path(config.path.clone());
+    let mut attempt = 1;
+    let mut merged_state = session_state.clone();
 
-    if let Some(max_age) = config.max_age {
-        cookie.set_max_age(max_age);
+    loop {
+        let result = storage_backend
+            .update_versioned(session_key.clone(), merged_state.clone(), &version, ttl)
+            .await;
+
+        let original_state = match (result, &original_state) {
+            (Ok(session_key), _) => return Ok(session_key),
+            (Err(UpdateError::Conflict), Some(original_state)) if attempt < MAX_UPDATE_ATTEMPTS => {
+                original_state
+            }
+            (Err(UpdateError::Conflict), _) => {
+                return Err(e409(anyhow::anyhow!(
+                    "The session state has been modified by a concurrent request"
+                )));
+            }
+            (Err(err), _) => return Err(e500(err)),
+        };
+
+        tracing::debug!("The session state has been modified concurrently, merging changes.");
+        attempt += 1;
+
+        match storage_backend.load_versioned(&session_key).await {
+            Ok(Some(VersionedSessionState {
+                state,
+                version: Some(latest_version),
+            })) => {
+                merged_state = merge_changes(original_state, &session_state, state);
+                version = latest_version;
+            }
+
+            // the session state is gone (or no longer versioned): there is nothing left to merge
+            // with
+            Ok(_) | Err(LoadError::Deserialization(_)) => {
+                return storage_backend
+                    .update(session_key, session_state, ttl)
+                    .await
+                    .map_err(e500);
+            }
+
+            Err(err) => return Err(e500(err)),
+        }
     }
+}
 
-    if let Some(ref domain) = config.domain {
-        cookie.set_domain(domain.clone());
+/// Applies the changes that turned `original` into `updated` on top of `latest`.
+fn merge_changes(
+    original: &SessionState,
+    updated: &SessionState,
+    mut latest: SessionState,
+) -> SessionState {
+    latest.retain(|key, _| !original.contains_key(key) || updated.contains_key(key));
+
+    for (key, value) in updated {
+        if original.get(key) != Some(value) {
+            latest.insert(key.clone(), value.clone());
+        }
     }
 
+    latest
+}
+
+/// Attaches the session key to the outgoing response, according to the configured
+/// [`SessionTransport`].
+fn set_session_key(
+    response: &mut ResponseHead,
+    session_key: SessionKey,
+    configuration: &Configuration,
+    incoming_cookies: usize,
+) -> Result<(), anyhow::Error> {
+    match &configuration.transport {
+        SessionTransport::Cookie => set_session_cookie(
+            response,
+            session_key,
+            &configuration.cookie,
+            incoming_cookies,
+        ),
+        SessionTransport::Header(transport) => {
+            set_session_header(response, session_key, transport, &configuration.cookie)
+        }
+    }
+}
+
+/// Instructs the client to discard its session key, according to the configured
+/// [`SessionTransport`].
+fn delete_session_key(
+    response: &mut ResponseHead,
+    configuration: &Configuration,
+    incoming_cookies: usize,
+) -> Result<(), anyhow::Error> {
+    match &configuration.transport {
+        SessionTransport::Cookie => {
+            delete_session_cookie(response, &configuration.cookie, incoming_cookies)
+        }
+        SessionTransport::Header(transport) => {
+            // an empty session header tells the client to forget the session key
+            response.headers_mut().insert(
+                transport.outgoing_header_name().clone(),
+                HeaderValue::from_static(""),
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Signs (or encrypts) the session key and attaches it to the session response header.
+fn set_session_header(
+    response: &mut ResponseHead,
+    session_key: SessionKey,
+    transport: &HeaderTransport,
+    config: &CookieConfiguration,
+) -> Result<(), anyhow::Error> {
+    let value: String = session_key.into();
+    let cookie = Cookie::new(transport.header_name.as_str().to_owned(), value);
+
     let mut jar = CookieJar::new();
     match config.content_security {
         CookieContentSecurity::Signed => jar.signed_mut(&config.key).add(cookie),
         CookieContentSecurity::Private => jar.private_mut(&config.key).add(cookie),
     }
 
-    // set cookie
     let cookie = jar.delta().next().unwrap();
-    let val = HeaderValue::from_str(&cookie.encoded().to_string())
-        .context("Failed to attach a session cookie to the outgoing response")?;
+    let val = HeaderValue::from_str(cookie.value())
+        .context("Failed to attach the session key to the outgoing response")?;
+    response
+        .headers_mut()
+        .insert(transport.outgoing_header_name().clone(), val);
 
-    response.headers_mut().append(SET_COOKIE, val);
+    Ok(())
+}
+
+fn set_session_cookie(
+    response: &mut ResponseHead,
+    session_key: SessionKey,
+    config: &CookieConfiguration,
+    incoming_cookies: usize,
+) -> Result<(), anyhow::Error> {
+    let value: String = session_key.into();
+    let chunks = split_session_key(&value, config)?;
+
+    for (chunk_index, chunk) in chunks.iter().enumerate() {
+        let mut cookie = Cookie::new(chunk_cookie_name(config, chunk_index), chunk.clone());
+
+        cookie.set_secure(config.secure);
+        cookie.set_http_only(config.http_only);
+        cookie.set_same_site(config.same_site);
+        cookie.set_path(config.path.clone());
+
+        if let Some(max_age) = config.max_age {
+            cookie.set_max_age(max_age);
+        }
+
+        if let Some(ref domain) = config.domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        let mut jar = CookieJar::new();
+        match config.content_security {
+            CookieContentSecurity::Signed => jar.signed_mut(&config.key).add(cookie),
+            CookieContentSecurity::Private => jar.private_mut(&config.key).add(cookie),
+        }
+
+        // set cookie
+        let cookie = jar.delta().next().unwrap();
+        let val = HeaderValue::from_str(&set_cookie_value(cookie.encoded().to_string(), config))
+            .context("Failed to attach a session cookie to the outgoing response")?;
+
+        response.headers_mut().append(SET_COOKIE, val);
+    }
+
+    // remove the chunks of a previous, longer, session key
+    for chunk_index in chunks.len()..incoming_cookies {
+        append_removal_cookie(response, config, chunk_index)?;
+    }
 
     Ok(())
 }
@@ -441,11 +1371,32 @@ fn set_session_cookie(
 fn delete_session_cookie(
     response: &mut ResponseHead,
     config: &CookieConfiguration,
+    incoming_cookies: usize,
 ) -> Result<(), anyhow::Error> {
-    let removal_cookie = Cookie::build(config.name.clone(), "")
+    for chunk_index in 0..incoming_cookies.max(1) {
+        append_removal_cookie(response, config, chunk_index)?;
+    }
+
+    Ok(())
+}
+
+fn append_removal_cookie(
+    response: &mut ResponseHead,
+    config: &CookieConfiguration,
+    chunk_index: usize,
+) -> Result<(), anyhow::Error> {
+    let removal_cookie = Cookie::build(chunk_cookie_name(config, chunk_index), "")
         .path(config.path.clone())
         .http_only(config.http_only);
 
+    // prefixed and partitioned cookies can only be removed by a cookie meeting the same
+    // requirements
+    let removal_cookie = if config.partitioned || CookiePrefix::of(&config.name).is_some() {
+        removal_cookie.secure(true)
+    } else {
+        removal_cookie
+    };
+
     let mut removal_cookie = if let Some(ref domain) = config.domain {
         removal_cookie.domain(domain)
     } else {
@@ -455,9 +1406,18 @@ fn delete_session_cookie(
 
     removal_cookie.make_removal();
 
-    let val = HeaderValue::from_str(&removal_cookie.to_string())
+    let val = HeaderValue::from_str(&set_cookie_value(removal_cookie.to_string(), config))
         .context("Failed to attach a session removal cookie to the outgoing response")?;
     response.headers_mut().append(SET_COOKIE, val);
 
     Ok(())
 }
+
+/// Appends the attributes `cookie` does not know about to a serialized session cookie.
+fn set_cookie_value(mut cookie: String, config: &CookieConfiguration) -> String {
+    if config.partitioned {
+        cookie.push_str("; Partitioned");
+    }
+
+    cookie
+}
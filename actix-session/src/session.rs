@@ -2,11 +2,13 @@ use std::{
     cell::{Ref, RefCell},
     collections::HashMap,
     error::Error as StdError,
+    future::Future,
+    marker::PhantomData,
     mem,
+    pin::Pin,
     rc::Rc,
 };
 
-use actix_utils::future::{ready, Ready};
 use actix_web::{
     body::BoxBody,
     dev::{Extensions, Payload, ServiceRequest, ServiceResponse},
@@ -17,6 +19,23 @@ use anyhow::Context;
 use derive_more::{Display, From};
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::{
+    flash::{FlashMessage, Level, FLASH_KEY},
+    middleware::{CREATED_AT_KEY, FINGERPRINT_KEY, LAST_ACCESS_KEY, SCHEMA_VERSION_KEY},
+    storage::SessionState,
+};
+
+/// The session state keys reserved for `actix-session`—see [`Session`].
+const RESERVED_KEYS: [&str; 6] = [
+    FLASH_KEY,
+    // the CSRF token, see the `csrf` module
+    "$csrf",
+    SCHEMA_VERSION_KEY,
+    CREATED_AT_KEY,
+    LAST_ACCESS_KEY,
+    FINGERPRINT_KEY,
+];
+
 /// The primary interface to access and modify session state.
 ///
 /// [`Session`] is an [extractor](#impl-FromRequest)—you can specify it as an input type for your
@@ -41,12 +60,68 @@ use serde::{de::DeserializeOwned, Serialize};
 /// You can also retrieve a [`Session`] object from an `HttpRequest` or a `ServiceRequest` using
 /// [`SessionExt`].
 ///
+/// # Reserved keys
+/// The following keys are reserved for `actix-session`, which uses them to store:
+///
+/// - flash messages (`$flash`);
+/// - the CSRF token of the session (`$csrf`);
+/// - the schema version of the session state (`$schema_version`);
+/// - when the session was created and last accessed (`$created_at` and `$last_access`);
+/// - the fingerprint of the client that created the session (`$fingerprint`).
+///
+/// [`Session::insert`] rejects them.
+///
+/// # Namespaces
+/// Each [`SessionMiddleware`] manages the sessions of a namespace, identified by a marker type—
+/// [`DefaultNamespace`] unless configured otherwise via
+/// [`SessionMiddlewareBuilder::namespace`]. A `Session<N>` is the session of namespace `N`: several
+/// [`SessionMiddleware`] instances, with their own cookie name, lifecycle and storage backend,
+/// can coexist in the same application as long as they use different namespaces. Namespaced
+/// sessions can also be retrieved using [`NamespacedSessionExt`].
+///
+/// ```
+/// use actix_session::Session;
+///
+/// /// Marker for the sessions of the back-office.
+/// struct Admin;
+///
+/// async fn dashboard(
+///     session: Session,
+///     admin_session: Session<Admin>,
+/// ) -> actix_web::Result<String> {
+///     let visitor_id = session.get::<String>("visitor_id")?;
+///     let admin_id = admin_session.get::<String>("admin_id")?;
+///     Ok(format!("{:?} {:?}", visitor_id, admin_id))
+/// }
+/// # actix_web::web::to(dashboard);
+/// ```
+///
 /// [`SessionExt`]: crate::SessionExt
-#[derive(Clone)]
-pub struct Session(Rc<RefCell<SessionInner>>);
+/// [`NamespacedSessionExt`]: crate::NamespacedSessionExt
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+/// [`SessionMiddlewareBuilder::namespace`]: crate::config::SessionMiddlewareBuilder::namespace
+pub struct Session<N = DefaultNamespace>(Rc<RefCell<SessionInner>>, PhantomData<fn() -> N>);
+
+impl<N> Clone for Session<N> {
+    fn clone(&self) -> Self {
+        Session(Rc::clone(&self.0), PhantomData)
+    }
+}
+
+/// The namespace of [`Session`]s managed by a [`SessionMiddleware`] whose
+/// [namespace](crate::config::SessionMiddlewareBuilder::namespace) was not configured.
+///
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+#[derive(Debug)]
+pub enum DefaultNamespace {}
+
+/// The request extension holding the session of namespace `N`.
+///
+/// Namespaces get a slot each in the request extensions, which are indexed by type.
+struct SessionSlot<N>(Rc<RefCell<SessionInner>>, PhantomData<fn() -> N>);
 
 /// Status of a [`Session`].
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum SessionStatus {
     /// Session state has been updated - the changes will have to be persisted to the backend.
     Changed,
@@ -64,33 +139,124 @@ pub enum SessionStatus {
     Renewed,
 
     /// The session state has not been modified since its creation/retrieval.
+    #[default]
     Unchanged,
 }
 
-impl Default for SessionStatus {
-    fn default() -> SessionStatus {
-        SessionStatus::Unchanged
+/// What happened when retrieving the session referenced by the incoming request.
+///
+/// See [`Session::load_outcome`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionLoadOutcome {
+    /// The request did not carry a session cookie (e.g. the user never logged in).
+    #[default]
+    Absent,
+
+    /// The session state was retrieved from the storage backend.
+    Loaded,
+
+    /// The session cookie is valid, but the session state it references could not be found.
+    ///
+    /// The session has most likely expired. Storage backends do not tell expired session states
+    /// apart from deleted ones—e.g. sessions invalidated on another device.
+    Expired,
+
+    /// The session cookie failed cryptographic checks (signature verification/decryption), or
+    /// was presented by a client whose fingerprint does not match the one the session was created
+    /// for.
+    Tampered,
+
+    /// The session state was found, but could not be deserialized or migrated to the current
+    /// [schema version](crate::config::SessionMiddlewareBuilder::state_migration).
+    Corrupted,
+}
+
+/// A deferred load of the session state from the storage backend.
+///
+/// Alongside the session state, it returns the outcome of the load—`None` if there was no session
+/// key to load the state of.
+pub(crate) type SessionStateLoader =
+    Pin<Box<dyn Future<Output = Result<(SessionState, Option<SessionLoadOutcome>), Error>>>>;
+
+/// A change made to the session state before it was loaded from the storage backend.
+pub(crate) enum StateChange {
+    Insert(String, serde_json::Value),
+    /// Appends a value to the array stored under the key.
+    Append(String, serde_json::Value),
+    Remove(String),
+    Clear,
+}
+
+/// Replays, over the session state loaded from the storage backend, the changes made to the
+/// session before it was loaded.
+pub(crate) fn replay_changes(session_state: &mut SessionState, changes: Vec<StateChange>) {
+    for change in changes {
+        match change {
+            StateChange::Insert(key, value) => {
+                session_state.insert(key, value);
+            }
+            StateChange::Append(key, value) => append(session_state, key, value),
+            StateChange::Remove(key) => {
+                session_state.remove(&key);
+            }
+            StateChange::Clear => session_state.clear(),
+        }
+    }
+}
+
+/// Appends `value` to the array stored under `key`, which is created if it is missing (or replaced
+/// if it does not hold an array).
+fn append(session_state: &mut SessionState, key: String, value: serde_json::Value) {
+    match session_state
+        .entry(key)
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()))
+    {
+        serde_json::Value::Array(values) => values.push(value),
+        other => *other = serde_json::Value::Array(vec![value]),
     }
 }
 
 #[derive(Default)]
 struct SessionInner {
-    state: HashMap<String, String>,
+    state: SessionState,
     status: SessionStatus,
+    loader: Option<SessionStateLoader>,
+    /// The changes made to the session state while the loader is pending.
+    pending_changes: Vec<StateChange>,
+    load_outcome: SessionLoadOutcome,
+}
+
+impl SessionInner {
+    /// Records a change to the session state, to be replayed once the state has been loaded.
+    fn record(&mut self, change: StateChange) {
+        if self.loader.is_some() {
+            self.pending_changes.push(change);
+        }
+    }
 }
 
-impl Session {
+impl<N: 'static> Session<N> {
     /// Get a `value` from the session.
     ///
-    /// It returns an error if it fails to deserialize as `T` the JSON value associated with `key`.
+    /// It returns an error if it fails to deserialize as `T` the JSON value associated with `key`,
+    /// or if the session has not been [loaded](Self::load) yet.
     pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SessionGetError> {
-        if let Some(val_str) = self.0.borrow().state.get(key) {
+        let inner = self.0.borrow();
+
+        if inner.loader.is_some() {
+            return Err(SessionGetError(anyhow::anyhow!(
+                "The session state has not been loaded yet: call `Session::load` before reading \
+                from a session retrieved via `SessionExt`"
+            )));
+        }
+
+        if let Some(val) = inner.state.get(key) {
             Ok(Some(
-                serde_json::from_str(val_str)
+                T::deserialize(val)
                     .with_context(|| {
                         format!(
-                            "Failed to deserialize the JSON-encoded session data attached to key \
-                            `{}` as a `{}` type",
+                            "Failed to deserialize the session data attached to key `{}` as a \
+                            `{}` type",
                             key,
                             std::any::type_name::<T>()
                         )
@@ -102,11 +268,23 @@ impl Session {
         }
     }
 
-    /// Get all raw key-value data from the session.
+    /// Get all key-value data from the session.
     ///
-    /// Note that values are JSON encoded.
-    pub fn entries(&self) -> Ref<'_, HashMap<String, String>> {
-        Ref::map(self.0.borrow(), |inner| &inner.state)
+    /// Values are represented as [`serde_json::Value`]s.
+    ///
+    /// Until the session has been [loaded](Self::load), only the entries inserted by the current
+    /// request are returned.
+    pub fn entries(&self) -> Ref<'_, SessionState> {
+        let inner = self.0.borrow();
+
+        if inner.loader.is_some() {
+            tracing::warn!(
+                "The entries of a session were read before its state was loaded: call \
+                `Session::load` before reading from a session retrieved via `SessionExt`."
+            );
+        }
+
+        Ref::map(inner, |inner| &inner.state)
     }
 
     /// Returns session status.
@@ -114,16 +292,49 @@ impl Session {
         Ref::map(self.0.borrow(), |inner| &inner.status).clone()
     }
 
+    /// Returns what happened when retrieving the session referenced by the incoming request.
+    ///
+    /// It lets you tell apart users whose session expired from users who never had one, in order
+    /// to display an appropriate message. In every case but [`SessionLoadOutcome::Loaded`], the
+    /// session starts out empty.
+    ///
+    /// If [lazy loading] is enabled, the outcome is only known once the session has been
+    /// [loaded](Self::load)—the [`Session`] extractor takes care of it for you.
+    ///
+    /// [lazy loading]: crate::config::SessionMiddlewareBuilder::lazy_session_loading
+    pub fn load_outcome(&self) -> SessionLoadOutcome {
+        self.0.borrow().load_outcome
+    }
+
     /// Inserts a key-value pair into the session.
     ///
-    /// Any serializable value can be used and will be encoded as JSON in session data, hence why
-    /// only a reference to the value is taken.
+    /// Any serializable value can be used and will be converted to a [`serde_json::Value`] in
+    /// session data, hence why only a reference to the value is taken.
     ///
-    /// It returns an error if it fails to serialize `value` to JSON.
+    /// It returns an error if it fails to serialize `value` to JSON, or if `key` is
+    /// [reserved](Self#reserved-keys).
     pub fn insert<T: Serialize>(
         &self,
         key: impl Into<String>,
         value: T,
+    ) -> Result<(), SessionInsertError> {
+        let key = key.into();
+
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            return Err(SessionInsertError(anyhow::anyhow!(
+                "The `{}` key is reserved: it is used by actix-session",
+                key
+            )));
+        }
+
+        self.insert_entry(key, value)
+    }
+
+    /// Inserts a key-value pair into the session, even if the key is reserved.
+    pub(crate) fn insert_entry<T: Serialize>(
+        &self,
+        key: String,
+        value: T,
     ) -> Result<(), SessionInsertError> {
         let mut inner = self.0.borrow_mut();
 
@@ -132,8 +343,7 @@ impl Session {
                 inner.status = SessionStatus::Changed;
             }
 
-            let key = key.into();
-            let val = serde_json::to_string(&value)
+            let val = serde_json::to_value(&value)
                 .with_context(|| {
                     format!(
                         "Failed to serialize the provided `{}` type instance as JSON in order to \
@@ -144,22 +354,63 @@ impl Session {
                 })
                 .map_err(SessionInsertError)?;
 
+            inner.record(StateChange::Insert(key.clone(), val.clone()));
             inner.state.insert(key, val);
         }
 
         Ok(())
     }
 
+    /// Adds a flash message to the session, to be read on a subsequent request using the
+    /// [`FlashMessages`] extractor.
+    ///
+    /// Any serializable value can be used as message. It returns an error if it fails to
+    /// serialize `message` to JSON. See the [`flash`](crate::flash) module for more details.
+    ///
+    /// Messages can be added before the session has been [loaded](Self::load): they are appended
+    /// to the messages added by previous requests once it is.
+    ///
+    /// [`FlashMessages`]: crate::flash::FlashMessages
+    pub fn flash<T: Serialize>(&self, level: Level, message: T) -> Result<(), SessionInsertError> {
+        let content = serde_json::to_value(&message)
+            .with_context(|| {
+                format!(
+                    "Failed to serialize the provided `{}` type instance as JSON in order to \
+                    attach it as a flash message",
+                    std::any::type_name::<T>()
+                )
+            })
+            .map_err(SessionInsertError)?;
+
+        let message = FlashMessage::new(level, content).to_value();
+        let mut inner = self.0.borrow_mut();
+
+        if inner.status != SessionStatus::Purged {
+            if inner.status != SessionStatus::Renewed {
+                inner.status = SessionStatus::Changed;
+            }
+
+            // the messages added by previous requests are kept if the session is not loaded yet
+            inner.record(StateChange::Append(FLASH_KEY.to_owned(), message.clone()));
+            append(&mut inner.state, FLASH_KEY.to_owned(), message);
+        }
+
+        Ok(())
+    }
+
     /// Remove value from the session.
     ///
-    /// If present, the JSON encoded value is returned.
-    pub fn remove(&self, key: &str) -> Option<String> {
+    /// If present, the JSON value is returned. The value is removed from the session state even if
+    /// the session has not been [loaded](Self::load) yet, but it can only be returned if it was
+    /// inserted by the current request.
+    pub fn remove(&self, key: &str) -> Option<serde_json::Value> {
         let mut inner = self.0.borrow_mut();
 
         if inner.status != SessionStatus::Purged {
             if inner.status != SessionStatus::Renewed {
                 inner.status = SessionStatus::Changed;
             }
+            inner.record(StateChange::Remove(key.to_owned()));
             return inner.state.remove(key);
         }
 
@@ -169,21 +420,23 @@ impl Session {
     /// Remove value from the session and deserialize.
     ///
     /// Returns `None` if key was not present in session. Returns `T` if deserialization succeeds,
-    /// otherwise returns un-deserialized JSON string.
-    pub fn remove_as<T: DeserializeOwned>(&self, key: &str) -> Option<Result<T, String>> {
-        self.remove(key)
-            .map(|val_str| match serde_json::from_str(&val_str) {
-                Ok(val) => Ok(val),
-                Err(_err) => {
-                    tracing::debug!(
-                        "Removed value (key: {}) could not be deserialized as {}",
-                        key,
-                        std::any::type_name::<T>()
-                    );
-
-                    Err(val_str)
-                }
-            })
+    /// otherwise returns the un-deserialized JSON value.
+    pub fn remove_as<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Option<Result<T, serde_json::Value>> {
+        self.remove(key).map(|val| match T::deserialize(&val) {
+            Ok(val) => Ok(val),
+            Err(_err) => {
+                tracing::debug!(
+                    "Removed value (key: {}) could not be deserialized as {}",
+                    key,
+                    std::any::type_name::<T>()
+                );
+
+                Err(val)
+            }
+        })
     }
 
     /// Clear the session.
@@ -194,6 +447,7 @@ impl Session {
             if inner.status != SessionStatus::Renewed {
                 inner.status = SessionStatus::Changed;
             }
+            inner.record(StateChange::Clear);
             inner.state.clear()
         }
     }
@@ -206,40 +460,95 @@ impl Session {
     }
 
     /// Renews the session key, assigning existing session state to new key.
+    ///
+    /// When the `csrf` feature is enabled, the CSRF token is rotated as well.
     pub fn renew(&self) {
         let mut inner = self.0.borrow_mut();
 
         if inner.status != SessionStatus::Purged {
             inner.status = SessionStatus::Renewed;
+
+            #[cfg(feature = "csrf")]
+            {
+                inner.record(StateChange::Remove(crate::csrf::CSRF_KEY.to_owned()));
+                inner.state.remove(crate::csrf::CSRF_KEY);
+            }
         }
     }
 
+    /// Loads the session state from the storage backend, if it has not been loaded yet.
+    ///
+    /// This is only needed when [lazy loading] is enabled, for sessions retrieved via
+    /// [`SessionExt`]—the [`Session`] extractor takes care of it for you. Changes made to the
+    /// session before it was loaded (insertions, removals, clears) are replayed over the loaded
+    /// state.
+    ///
+    /// [lazy loading]: crate::config::SessionMiddlewareBuilder::lazy_session_loading
+    /// [`SessionExt`]: crate::SessionExt
+    pub async fn load(&self) -> Result<(), Error> {
+        let loader = self.0.borrow_mut().loader.take();
+
+        if let Some(loader) = loader {
+            let (state, load_outcome) = loader.await?;
+            let mut inner = self.0.borrow_mut();
+
+            if let Some(load_outcome) = load_outcome {
+                inner.load_outcome = load_outcome;
+            }
+
+            let changes = mem::take(&mut inner.pending_changes);
+            if inner.status != SessionStatus::Purged {
+                inner.state = state;
+                replay_changes(&mut inner.state, changes);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Adds the given key-value pairs to the session on the request.
     ///
-    /// Values that match keys already existing on the session will be overwritten. Values should
-    /// already be JSON serialized.
+    /// Values that match keys already existing on the session will be overwritten.
     pub(crate) fn set_session(
-        req: &mut ServiceRequest,
-        data: impl IntoIterator<Item = (String, String)>,
+        extensions: &mut Extensions,
+        data: impl IntoIterator<Item = (String, serde_json::Value)>,
     ) {
-        let session = Session::get_session(&mut *req.extensions_mut());
+        let session = Self::get_session(extensions);
         let mut inner = session.0.borrow_mut();
         inner.state.extend(data);
     }
 
+    /// Records what happened when retrieving the session referenced by the request.
+    pub(crate) fn set_load_outcome(extensions: &mut Extensions, load_outcome: SessionLoadOutcome) {
+        let session = Self::get_session(extensions);
+        session.0.borrow_mut().load_outcome = load_outcome;
+    }
+
+    /// Defers loading the session state of the request until the session is accessed.
+    pub(crate) fn set_loader(req: &mut ServiceRequest, loader: SessionStateLoader) {
+        let session = Self::get_session(&mut *req.extensions_mut());
+        session.0.borrow_mut().loader = Some(loader);
+    }
+
+    /// Returns the deferred session state load of the request, if the session was never loaded,
+    /// alongside the changes made to the session in the meantime.
+    pub(crate) fn take_loader<B>(
+        res: &mut ServiceResponse<B>,
+    ) -> Option<(SessionStateLoader, Vec<StateChange>)> {
+        let extensions = res.request().extensions();
+        let mut inner = extensions.get::<SessionSlot<N>>()?.0.borrow_mut();
+        let loader = inner.loader.take()?;
+
+        Some((loader, mem::take(&mut inner.pending_changes)))
+    }
+
     /// Returns session status and iterator of key-value pairs of changes.
     ///
     /// This is a destructive operation - the session state is removed from the request extensions
     /// typemap, leaving behind a new empty map. It should only be used when the session is being
     /// finalised (i.e. in `SessionMiddleware`).
-    pub(crate) fn get_changes<B>(
-        res: &mut ServiceResponse<B>,
-    ) -> (SessionStatus, HashMap<String, String>) {
-        if let Some(s_impl) = res
-            .request()
-            .extensions()
-            .get::<Rc<RefCell<SessionInner>>>()
-        {
+    pub(crate) fn get_changes<B>(res: &mut ServiceResponse<B>) -> (SessionStatus, SessionState) {
+        if let Some(SessionSlot(s_impl, _)) = res.request().extensions().get::<SessionSlot<N>>() {
             let state = mem::take(&mut s_impl.borrow_mut().state);
             (s_impl.borrow().status.clone(), state)
         } else {
@@ -247,20 +556,23 @@ impl Session {
         }
     }
 
-    pub(crate) fn get_session(extensions: &mut Extensions) -> Session {
-        if let Some(s_impl) = extensions.get::<Rc<RefCell<SessionInner>>>() {
-            return Session(Rc::clone(s_impl));
+    pub(crate) fn get_session(extensions: &mut Extensions) -> Session<N> {
+        if let Some(SessionSlot(s_impl, _)) = extensions.get::<SessionSlot<N>>() {
+            return Session(Rc::clone(s_impl), PhantomData);
         }
 
         let inner = Rc::new(RefCell::new(SessionInner::default()));
-        extensions.insert(inner.clone());
+        extensions.insert(SessionSlot::<N>(Rc::clone(&inner), PhantomData));
 
-        Session(inner)
+        Session(inner, PhantomData)
     }
 }
 
 /// Extractor implementation for [`Session`]s.
 ///
+/// If [lazy loading] is enabled, the session state is loaded from the storage backend on
+/// extraction.
+///
 /// # Examples
 /// ```
 /// # use actix_web::*;
@@ -279,13 +591,20 @@ impl Session {
 ///     Ok(format!("Counter: {}", count))
 /// }
 /// ```
-impl FromRequest for Session {
+///
+/// [lazy loading]: crate::config::SessionMiddlewareBuilder::lazy_session_loading
+impl<N: 'static> FromRequest for Session<N> {
     type Error = Error;
-    type Future = Ready<Result<Session, Error>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Session<N>, Error>>>>;
 
     #[inline]
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        ready(Ok(Session::get_session(&mut *req.extensions_mut())))
+        let session = Self::get_session(&mut *req.extensions_mut());
+
+        Box::pin(async move {
+            session.load().await?;
+            Ok(session)
+        })
     }
 }
 
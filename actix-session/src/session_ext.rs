@@ -15,13 +15,13 @@ pub trait SessionExt {
 
 impl SessionExt for HttpRequest {
     fn get_session(&self) -> Session {
-        Session::get_session(&mut *self.extensions_mut())
+        self.get_namespaced_session()
     }
 }
 
 impl SessionExt for ServiceRequest {
     fn get_session(&self) -> Session {
-        Session::get_session(&mut *self.extensions_mut())
+        self.get_namespaced_session()
     }
 }
 
@@ -33,6 +33,37 @@ impl SessionExt for ServiceResponse {
 
 impl<'a> SessionExt for GuardContext<'a> {
     fn get_session(&self) -> Session {
+        self.get_namespaced_session()
+    }
+}
+
+/// Extract the [`Session`] object of a [namespace](Session#namespaces) from various `actix-web`
+/// types (e.g. `HttpRequest`, `ServiceRequest`, `ServiceResponse`).
+pub trait NamespacedSessionExt {
+    /// Extract the [`Session`] object of namespace `N`.
+    fn get_namespaced_session<N: 'static>(&self) -> Session<N>;
+}
+
+impl NamespacedSessionExt for HttpRequest {
+    fn get_namespaced_session<N: 'static>(&self) -> Session<N> {
+        Session::get_session(&mut *self.extensions_mut())
+    }
+}
+
+impl NamespacedSessionExt for ServiceRequest {
+    fn get_namespaced_session<N: 'static>(&self) -> Session<N> {
+        Session::get_session(&mut *self.extensions_mut())
+    }
+}
+
+impl NamespacedSessionExt for ServiceResponse {
+    fn get_namespaced_session<N: 'static>(&self) -> Session<N> {
+        self.request().get_namespaced_session()
+    }
+}
+
+impl<'a> NamespacedSessionExt for GuardContext<'a> {
+    fn get_namespaced_session<N: 'static>(&self) -> Session<N> {
         Session::get_session(&mut *self.req_data_mut())
     }
 }
@@ -0,0 +1,556 @@
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+use actix_web::cookie::time::Duration;
+use anyhow::Error;
+
+use super::SessionKey;
+use crate::storage::{
+    interface::{LoadError, SaveError, SessionState, SessionVersion, UpdateError},
+    lru::LruMap,
+    IndexedSessionStore, SessionStore, VersionedSessionState,
+};
+
+/// Wraps a [`SessionStore`] with a read-through cache, kept in the memory of the current process.
+///
+/// Session states loaded from the wrapped store are cached for a short TTL: requests for a hot
+/// session are served from memory instead of hitting the storage backend every time.
+///
+/// ```no_run
+/// use actix_web::{cookie::Key, web, App, HttpServer, HttpResponse};
+/// use actix_session::{SessionMiddleware, storage::{CachedSessionStore, RedisSessionStore}};
+///
+/// #[actix_web::main]
+/// async fn main() -> std::io::Result<()> {
+///     let secret_key = Key::generate();
+///     let redis_store = RedisSessionStore::new("redis://127.0.0.1:6379").await.unwrap();
+///     let store = CachedSessionStore::new(redis_store);
+///
+///     HttpServer::new(move ||
+///             App::new()
+///             .wrap(SessionMiddleware::new(store.clone(), secret_key.clone()))
+///             .default_service(web::to(|| HttpResponse::Ok())))
+///         .bind(("127.0.0.1", 8080))?
+///         .run()
+///         .await
+/// }
+/// ```
+///
+/// Clones of a [`CachedSessionStore`] share the same cache—create the store once, outside of the
+/// `HttpServer::new` closure, and pass a clone to each worker.
+///
+/// # Consistency
+/// Cached session states are invalidated when they are updated or deleted through the cache—
+/// invalidation happens once the wrapped store has been written to, so that concurrent loads do
+/// not cache the previous state.
+/// Changes made by other instances of your application (or directly in the storage backend) are
+/// not visible until the cached copy expires—at most [`ttl`](CachedSessionStoreBuilder::ttl)
+/// after it was loaded. Keep the TTL short, or invalidate cached states explicitly via
+/// [`CachedSessionStore::invalidate`]. If you are using Redis, the cache can be kept in sync
+/// across instances using [keyspace notifications](CachedSessionStore::invalidate_on_keyspace_notifications).
+///
+/// Concurrent updates are still detected if the wrapped store supports versioning: a conflicting
+/// update invalidates the stale cached copy.
+///
+/// # Eviction
+/// The cache holds at most [`max_capacity`](CachedSessionStoreBuilder::max_capacity) session
+/// states. When it is full, expired cached states are removed first; if none has expired, the
+/// least recently used cached state is evicted.
+pub struct CachedSessionStore<S> {
+    store: S,
+    cache: Arc<Mutex<Cache>>,
+    ttl: std::time::Duration,
+}
+
+impl<S: Clone> Clone for CachedSessionStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            cache: Arc::clone(&self.cache),
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl<S: SessionStore> CachedSessionStore<S> {
+    /// A fluent API to configure [`CachedSessionStore`].
+    pub fn builder(store: S) -> CachedSessionStoreBuilder<S> {
+        CachedSessionStoreBuilder {
+            store,
+            max_capacity: default_max_capacity(),
+            ttl: default_ttl(),
+        }
+    }
+
+    /// Wrap `store` with a cache, using the default configuration.
+    pub fn new(store: S) -> CachedSessionStore<S> {
+        Self::builder(store).build()
+    }
+
+    /// Returns the wrapped store.
+    ///
+    /// Changes made through the wrapped store bypass the cache—invalidate the affected session
+    /// states via [`CachedSessionStore::invalidate`].
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Removes the session state attached to `session_key` from the cache, if it is cached.
+    ///
+    /// The next load goes to the wrapped store.
+    pub fn invalidate(&self, session_key: &SessionKey) {
+        self.cache().invalidate(session_key.as_ref());
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        lock(&self.cache)
+    }
+}
+
+fn lock(cache: &Mutex<Cache>) -> MutexGuard<'_, Cache> {
+    // The cache is left in a consistent state even if a thread panicked while holding the lock.
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+const fn default_max_capacity() -> usize {
+    10_000
+}
+
+const fn default_ttl() -> Duration {
+    Duration::seconds(5)
+}
+
+/// A fluent builder to construct a [`CachedSessionStore`] instance with custom configuration
+/// parameters.
+#[must_use]
+pub struct CachedSessionStoreBuilder<S> {
+    store: S,
+    max_capacity: usize,
+    ttl: Duration,
+}
+
+impl<S: SessionStore> CachedSessionStoreBuilder<S> {
+    /// Set the maximum number of session states kept in the cache.
+    ///
+    /// When the cache is full, the least recently used cached state is evicted.
+    ///
+    /// Defaults to 10,000.
+    ///
+    /// # Panics
+    /// Panics if `max_capacity` is `0`.
+    pub fn max_capacity(mut self, max_capacity: usize) -> Self {
+        assert!(
+            max_capacity > 0,
+            "The session state cache must be able to hold at least one session state"
+        );
+        self.max_capacity = max_capacity;
+        self
+    }
+
+    /// Set how long a session state is served from the cache after it was loaded from the wrapped
+    /// store.
+    ///
+    /// It bounds how stale a cached session state can get when it is modified by another instance
+    /// of your application.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Finalise the builder and return a [`CachedSessionStore`] instance.
+    #[must_use]
+    pub fn build(self) -> CachedSessionStore<S> {
+        CachedSessionStore {
+            store: self.store,
+            cache: Arc::new(Mutex::new(Cache::new(self.max_capacity))),
+            // negative durations are treated as zero—nothing is cached
+            ttl: std::time::Duration::try_from(self.ttl).unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<S: SessionStore> SessionStore for CachedSessionStore<S> {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        Ok(self
+            .load_versioned(session_key)
+            .await?
+            .map(|versioned| versioned.state))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        self.store.save(session_state, ttl).await
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let result = self
+            .store
+            .update(session_key.clone(), session_state, ttl)
+            .await;
+        self.invalidate(&session_key);
+        result
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), Error> {
+        self.store.update_ttl(session_key, ttl).await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), Error> {
+        let result = self.store.delete(session_key).await;
+        self.invalidate(session_key);
+        result
+    }
+
+    async fn load_versioned(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<VersionedSessionState>, LoadError> {
+        let invalidations = {
+            let mut cache = self.cache();
+            if let Some(state) = cache.get(session_key.as_ref(), Instant::now()) {
+                return Ok(Some(state));
+            }
+            cache.invalidations
+        };
+
+        let state = self.store.load_versioned(session_key).await?;
+
+        if let Some(state) = &state {
+            let mut cache = self.cache();
+            // the state might have been modified while it was being loaded, in which case the
+            // loaded copy is already stale
+            if cache.invalidations == invalidations {
+                cache.insert(
+                    session_key.as_ref().to_owned(),
+                    state.clone(),
+                    Instant::now() + self.ttl,
+                );
+            }
+        }
+
+        Ok(state)
+    }
+
+    async fn update_versioned(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        version: &SessionVersion,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let result = self
+            .store
+            .update_versioned(session_key.clone(), session_state, version, ttl)
+            .await;
+        // on conflicts, the cached copy is stale: the state must be reloaded from the wrapped store
+        self.invalidate(&session_key);
+        result
+    }
+}
+
+/// Session states are looked up in the wrapped store, bypassing the cache.
+#[async_trait::async_trait(?Send)]
+impl<S: IndexedSessionStore> IndexedSessionStore for CachedSessionStore<S> {
+    async fn sessions_for(&self, subject: &str) -> Result<Vec<SessionKey>, Error> {
+        self.store.sessions_for(subject).await
+    }
+
+    async fn delete_all_for(&self, subject: &str) -> Result<usize, Error> {
+        let session_keys = self.store.sessions_for(subject).await?;
+        let deleted = self.store.delete_all_for(subject).await?;
+
+        let mut cache = self.cache();
+        for session_key in &session_keys {
+            cache.invalidate(session_key.as_ref());
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(feature = "redis-rs-session")]
+impl<S: SessionStore> CachedSessionStore<S> {
+    /// Subscribes to Redis keyspace notifications, to invalidate cached session states as soon as
+    /// they are modified or deleted—by any instance of your application.
+    ///
+    /// Only the keys starting with `key_prefix`, in the database selected by `client`, are
+    /// watched: the session key is the remainder of the Redis key. Use the prefix prepended by the
+    /// function passed to [`RedisSessionStoreBuilder::cache_keygen`], if any—or an empty prefix.
+    ///
+    /// Keyspace notifications must be enabled on the Redis server, for generic and string
+    /// commands as well as expired events—e.g. `CONFIG SET notify-keyspace-events Kg$x`.
+    ///
+    /// The subscription runs in a background task on the current Actix Web worker; it stops on
+    /// its own once the store and all its clones have been dropped. If the connection to Redis is
+    /// lost, the whole cache is cleared and the subscription ends.
+    ///
+    /// ```no_run
+    /// use actix_session::storage::{CachedSessionStore, RedisSessionStore};
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let redis_store = RedisSessionStore::builder("redis://127.0.0.1:6379")
+    ///     .cache_keygen(|session_key| format!("session:{}", session_key))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// let store = CachedSessionStore::new(redis_store);
+    ///
+    /// let client = redis::Client::open("redis://127.0.0.1:6379").unwrap();
+    /// store
+    ///     .invalidate_on_keyspace_notifications(client, "session:")
+    ///     .await
+    ///     .unwrap();
+    /// # })
+    /// ```
+    ///
+    /// [`RedisSessionStoreBuilder::cache_keygen`]: crate::storage::RedisSessionStoreBuilder::cache_keygen
+    pub async fn invalidate_on_keyspace_notifications<P: Into<String>>(
+        &self,
+        client: redis::Client,
+        key_prefix: P,
+    ) -> Result<(), Error> {
+        let channel_prefix = format!(
+            "__keyspace@{}__:{}",
+            client.get_connection_info().redis.db,
+            key_prefix.into()
+        );
+
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        pubsub
+            .psubscribe(format!("{}*", escape_glob(&channel_prefix)))
+            .await?;
+
+        let cache = Arc::downgrade(&self.cache);
+        actix_web::rt::spawn(async move {
+            let mut messages = Box::pin(pubsub.into_on_message());
+
+            while let Some(message) = NextMessage(&mut messages).await {
+                let cache = match cache.upgrade() {
+                    Some(cache) => cache,
+                    None => return,
+                };
+
+                if let Some(session_key) = message.get_channel_name().strip_prefix(&channel_prefix)
+                {
+                    lock(&cache).invalidate(session_key);
+                }
+            }
+
+            tracing::warn!(
+                "The subscription to Redis keyspace notifications has ended, clearing the session \
+                state cache."
+            );
+            if let Some(cache) = cache.upgrade() {
+                lock(&cache).clear();
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Escapes the characters with a special meaning in Redis glob-style patterns.
+#[cfg(feature = "redis-rs-session")]
+fn escape_glob(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Resolves to the next item of a stream.
+#[cfg(feature = "redis-rs-session")]
+struct NextMessage<'a, St>(&'a mut St);
+
+#[cfg(feature = "redis-rs-session")]
+impl<St: futures_core::Stream + Unpin> std::future::Future for NextMessage<'_, St> {
+    type Output = Option<St::Item>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        std::pin::Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
+
+/// Cached session states, indexed by session key, evicted in least-recently-used order.
+struct Cache {
+    states: LruMap<VersionedSessionState>,
+    /// The number of invalidations so far, used to detect states modified while being loaded.
+    invalidations: u64,
+}
+
+impl Cache {
+    fn new(max_capacity: usize) -> Self {
+        Self {
+            states: LruMap::new(max_capacity),
+            invalidations: 0,
+        }
+    }
+
+    /// Returns a copy of the state attached to `key`, if it has not expired.
+    fn get(&mut self, key: &str, now: Instant) -> Option<VersionedSessionState> {
+        self.states.get(key, now).map(|cached| cached.value.clone())
+    }
+
+    fn insert(&mut self, key: String, state: VersionedSessionState, expires_at: Instant) {
+        self.states.insert(key, state, expires_at, Instant::now());
+    }
+
+    fn invalidate(&mut self, key: &str) {
+        self.invalidations += 1;
+        self.states.remove(key);
+    }
+
+    #[cfg(feature = "redis-rs-session")]
+    fn clear(&mut self) {
+        self.invalidations += 1;
+        self.states.clear();
+    }
+}
+
+#[cfg(all(test, feature = "in-memory-session"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        storage::InMemorySessionStore,
+        test_helpers::{
+            acceptance_test_suite, optimistic_concurrency_test_suite, subject_index_test_suite,
+        },
+    };
+
+    #[actix_web::test]
+    async fn test_session_workflow() {
+        let store = CachedSessionStore::new(InMemorySessionStore::new());
+        acceptance_test_suite(move || store.clone(), true).await;
+    }
+
+    #[actix_web::test]
+    async fn session_states_are_served_from_the_cache() {
+        let store = CachedSessionStore::new(InMemorySessionStore::new());
+        let mut state = HashMap::new();
+        state.insert("key".to_owned(), serde_json::json!("value"));
+        let session_key = store.save(state.clone(), &Duration::days(1)).await.unwrap();
+
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(state.clone()));
+
+        // changes bypassing the cache are not visible until the cached copy is invalidated
+        store.inner().delete(&session_key).await.unwrap();
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(state));
+
+        store.invalidate(&session_key);
+        assert_eq!(store.load(&session_key).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn cached_states_are_invalidated_on_updates_and_deletions() {
+        let store = CachedSessionStore::new(InMemorySessionStore::new());
+        let session_key = store
+            .save(HashMap::new(), &Duration::days(1))
+            .await
+            .unwrap();
+        store.load(&session_key).await.unwrap();
+
+        let mut state = HashMap::new();
+        state.insert("key".to_owned(), serde_json::json!("value"));
+        let session_key = store
+            .update(session_key, state.clone(), &Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(state));
+
+        store.delete(&session_key).await.unwrap();
+        assert_eq!(store.load(&session_key).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn cached_states_expire() {
+        let store = CachedSessionStore::builder(InMemorySessionStore::new())
+            .ttl(Duration::milliseconds(10))
+            .build();
+        let session_key = store
+            .save(HashMap::new(), &Duration::days(1))
+            .await
+            .unwrap();
+        store.load(&session_key).await.unwrap();
+
+        store.inner().delete(&session_key).await.unwrap();
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(store.load(&session_key).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn the_least_recently_used_state_is_evicted_when_full() {
+        let store = CachedSessionStore::builder(InMemorySessionStore::new())
+            .max_capacity(1)
+            .build();
+        let first = store
+            .save(HashMap::new(), &Duration::days(1))
+            .await
+            .unwrap();
+        let second = store
+            .save(HashMap::new(), &Duration::days(1))
+            .await
+            .unwrap();
+        store.load(&first).await.unwrap();
+        store.load(&second).await.unwrap();
+
+        store.inner().delete(&first).await.unwrap();
+        store.inner().delete(&second).await.unwrap();
+        assert_eq!(store.load(&first).await.unwrap(), None);
+        assert_eq!(store.load(&second).await.unwrap(), Some(HashMap::new()));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one session state")]
+    fn max_capacity_must_not_be_zero() {
+        let _ = CachedSessionStore::builder(InMemorySessionStore::new()).max_capacity(0);
+    }
+
+    #[cfg(feature = "redis-rs-session")]
+    #[test]
+    fn glob_patterns_are_escaped() {
+        assert_eq!(
+            escape_glob("__keyspace@0__:session:"),
+            "__keyspace@0__:session:"
+        );
+        assert_eq!(escape_glob("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+
+    #[actix_web::test]
+    async fn sessions_can_be_looked_up_and_deleted_by_subject() {
+        let store = CachedSessionStore::new(
+            InMemorySessionStore::builder()
+                .subject_key("user_id")
+                .build(),
+        );
+        subject_index_test_suite(store).await;
+    }
+
+    #[actix_web::test]
+    async fn concurrent_updates_are_detected() {
+        let store = CachedSessionStore::new(InMemorySessionStore::new());
+        optimistic_concurrency_test_suite(store).await;
+    }
+}
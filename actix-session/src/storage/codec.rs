@@ -0,0 +1,550 @@
+#[cfg(feature = "encrypted-codec")]
+use std::fmt;
+
+use super::interface::SessionState;
+
+/// The interface to turn session state into bytes, and back, for storage backends that persist it
+/// as an opaque blob (e.g. [`CookieSessionStore`], [`RedisSessionStore`] and
+/// [`RedisActorSessionStore`]).
+///
+/// `actix-session` ships with [`JsonCodec`], the default, and two binary codecs that produce more
+/// compact payloads: `MessagePackCodec` (`msgpack-codec` feature flag) and `CborCodec`
+/// (`cbor-codec` feature flag). You can provide your own by implementing this trait.
+///
+/// # Migrating between codecs
+/// Session state written by `actix-session` before a codec could be configured is always
+/// JSON-encoded. Storage backends fall back to decoding it as JSON if the configured codec
+/// rejects it, so you can switch to a different codec without logging out every user—sessions
+/// are re-encoded with the new codec the next time their state is updated. Codecs can opt out of
+/// this fallback via [`json_fallback`](Self::json_fallback).
+///
+/// Likewise, session states persisted by `actix-session` v0.7—whose values were JSON-encoded
+/// strings rather than arbitrary JSON values—keep loading as expected.
+///
+/// [`CookieSessionStore`]: crate::storage::CookieSessionStore
+/// [`RedisSessionStore`]: crate::storage::RedisSessionStore
+/// [`RedisActorSessionStore`]: crate::storage::RedisActorSessionStore
+pub trait SessionStateCodec: Send + Sync {
+    /// Encodes the session state into bytes.
+    fn encode(&self, session_state: &SessionState) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Decodes bytes produced by [`encode`](Self::encode) back into session state.
+    fn decode(&self, bytes: &[u8]) -> Result<SessionState, anyhow::Error>;
+
+    /// Whether storage backends should decode session states as plaintext JSON when
+    /// [`decode`](Self::decode) rejects them—see
+    /// [Migrating between codecs](Self#migrating-between-codecs).
+    ///
+    /// Defaults to `true`.
+    fn json_fallback(&self) -> bool {
+        true
+    }
+}
+
+/// Encodes session state as JSON.
+///
+/// This is the default codec for all storage backends.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl SessionStateCodec for JsonCodec {
+    fn encode(&self, session_state: &SessionState) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(serde_json::to_vec(session_state)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<SessionState, anyhow::Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Encodes session state as [MessagePack](https://msgpack.org).
+#[cfg(feature = "msgpack-codec")]
+#[cfg_attr(docsrs, doc(cfg(feature = "msgpack-codec")))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack-codec")]
+impl SessionStateCodec for MessagePackCodec {
+    fn encode(&self, session_state: &SessionState) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(rmp_serde::to_vec(session_state)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<SessionState, anyhow::Error> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// Encodes session state as [CBOR](https://cbor.io).
+#[cfg(feature = "cbor-codec")]
+#[cfg_attr(docsrs, doc(cfg(feature = "cbor-codec")))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor-codec")]
+impl SessionStateCodec for CborCodec {
+    fn encode(&self, session_state: &SessionState) -> Result<Vec<u8>, anyhow::Error> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(session_state, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<SessionState, anyhow::Error> {
+        Ok(ciborium::de::from_reader(bytes)?)
+    }
+}
+
+/// Encrypts the session state produced by another codec, for storage backends that should not
+/// hold it in plaintext (e.g. Redis).
+///
+/// The session state is encrypted using AES-256-GCM, with a key derived (via HKDF-SHA256) from
+/// an application secret and a key ID. The key ID is embedded in the ciphertext: secrets can be
+/// rotated without logging out every user by registering the previous secret as a
+/// [decryption key](Self::decryption_key) until the session states encrypted with it have expired.
+///
+/// ```
+/// use actix_web::cookie::Key;
+/// use actix_session::storage::{EncryptedCodec, JsonCodec};
+///
+/// // The secrets would usually be read from a configuration file/environment variables.
+/// let current_secret = Key::generate();
+/// let previous_secret = Key::generate();
+///
+/// let codec = EncryptedCodec::new("2022-06", current_secret.master())
+///     .codec(JsonCodec)
+///     .decryption_key("2022-01", previous_secret.master());
+/// ```
+///
+/// Pass it to the `codec` method of the builder of your storage backend—e.g.
+/// [`RedisSessionStoreBuilder::codec`].
+///
+/// # Migrating to encrypted session states
+/// Session states persisted in plaintext JSON are rejected by default: anyone able to write to
+/// your storage backend could otherwise forge them. To keep loading the session states persisted
+/// before encryption was enabled, turn on the [plaintext fallback](Self::plaintext_fallback) until
+/// they have all expired—they are encrypted the next time they are updated.
+///
+/// [`RedisSessionStoreBuilder::codec`]: crate::storage::RedisSessionStoreBuilder::codec
+#[cfg(feature = "encrypted-codec")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encrypted-codec")))]
+#[derive(Clone)]
+pub struct EncryptedCodec {
+    codec: std::sync::Arc<dyn SessionStateCodec>,
+    encryption_key: EncryptionKey,
+    decryption_keys: Vec<EncryptionKey>,
+    plaintext_fallback: bool,
+}
+
+#[cfg(feature = "encrypted-codec")]
+#[derive(Clone)]
+struct EncryptionKey {
+    id: String,
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "encrypted-codec")]
+impl EncryptionKey {
+    fn derive(id: String, secret: &[u8]) -> Self {
+        use aes_gcm::KeyInit as _;
+
+        assert!(
+            !id.is_empty() && id.len() <= usize::from(u8::MAX),
+            "The ID of an encryption key must be between 1 and 255 bytes long"
+        );
+
+        let mut key = [0; 32];
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(b"actix-session"), secret)
+            .expand(
+                format!("session state encryption: {}", id).as_bytes(),
+                &mut key,
+            )
+            .expect("32 bytes is a valid output length for HKDF-SHA256");
+
+        Self {
+            id,
+            cipher: aes_gcm::Aes256Gcm::new(&key.into()),
+        }
+    }
+}
+
+/// The first byte of the session states encrypted by [`EncryptedCodec`], identifying the format.
+///
+/// It can never be mistaken for the opening brace of a plaintext JSON session state.
+#[cfg(feature = "encrypted-codec")]
+const ENCRYPTED_FORMAT_VERSION: u8 = 1;
+
+#[cfg(feature = "encrypted-codec")]
+const NONCE_LENGTH: usize = 12;
+
+#[cfg(feature = "encrypted-codec")]
+impl EncryptedCodec {
+    /// Encrypt session states with a key derived from `secret`, identified by `key_id`.
+    ///
+    /// Use a different key ID every time you rotate the secret. The session state is encoded as
+    /// JSON before being encrypted, unless you set a different [codec](Self::codec).
+    ///
+    /// # Panics
+    /// Panics if `key_id` is empty or longer than 255 bytes.
+    pub fn new(key_id: impl Into<String>, secret: &[u8]) -> Self {
+        Self {
+            codec: std::sync::Arc::new(JsonCodec),
+            encryption_key: EncryptionKey::derive(key_id.into(), secret),
+            decryption_keys: Vec::new(),
+            plaintext_fallback: false,
+        }
+    }
+
+    /// Set the codec used to encode the session state before encrypting it.
+    ///
+    /// Defaults to [`JsonCodec`].
+    pub fn codec<C: SessionStateCodec + 'static>(mut self, codec: C) -> Self {
+        self.codec = std::sync::Arc::new(codec);
+        self
+    }
+
+    /// Register a key only used to decrypt session states—typically the one derived from the
+    /// secret used before the current one.
+    ///
+    /// # Panics
+    /// Panics if `key_id` is empty or longer than 255 bytes.
+    pub fn decryption_key(mut self, key_id: impl Into<String>, secret: &[u8]) -> Self {
+        self.decryption_keys
+            .push(EncryptionKey::derive(key_id.into(), secret));
+        self
+    }
+
+    /// Accept session states persisted in plaintext JSON, i.e. before encryption was enabled.
+    ///
+    /// Only enable it while migrating to encrypted session states: it lets anyone able to write to
+    /// your storage backend forge session states.
+    ///
+    /// Defaults to `false`.
+    pub fn plaintext_fallback(mut self, enabled: bool) -> Self {
+        self.plaintext_fallback = enabled;
+        self
+    }
+}
+
+#[cfg(feature = "encrypted-codec")]
+impl fmt::Debug for EncryptedCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedCodec")
+            .field("key_id", &self.encryption_key.id)
+            .field(
+                "decryption_key_ids",
+                &self
+                    .decryption_keys
+                    .iter()
+                    .map(|key| key.id.as_str())
+                    .collect::<Vec<_>>(),
+            )
+            .field("plaintext_fallback", &self.plaintext_fallback)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypted session states are laid out as follows: the format version (1 byte), the length of
+/// the key ID (1 byte), the key ID, the nonce (12 bytes) and the ciphertext, including the
+/// authentication tag. The format version and the key ID are authenticated alongside the
+/// ciphertext.
+#[cfg(feature = "encrypted-codec")]
+impl SessionStateCodec for EncryptedCodec {
+    fn encode(&self, session_state: &SessionState) -> Result<Vec<u8>, anyhow::Error> {
+        use aes_gcm::aead::{Aead as _, AeadCore as _, OsRng, Payload};
+
+        let plaintext = self.codec.encode(session_state)?;
+        let key = &self.encryption_key;
+
+        let mut bytes = vec![ENCRYPTED_FORMAT_VERSION, key.id.len() as u8];
+        bytes.extend_from_slice(key.id.as_bytes());
+
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &bytes,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt the session state"))?;
+
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<SessionState, anyhow::Error> {
+        use aes_gcm::aead::{Aead as _, Payload};
+
+        let (version, key_id_len) = match bytes {
+            [version, key_id_len, ..] => (*version, usize::from(*key_id_len)),
+            _ => anyhow::bail!("The encrypted session state is truncated"),
+        };
+        if version != ENCRYPTED_FORMAT_VERSION {
+            anyhow::bail!("Unknown encrypted session state format: {}", version);
+        }
+
+        let header_len = 2 + key_id_len;
+        if bytes.len() < header_len + NONCE_LENGTH {
+            anyhow::bail!("The encrypted session state is truncated");
+        }
+        let (header, rest) = bytes.split_at(header_len);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+        let key_id = &header[2..];
+        let key = std::iter::once(&self.encryption_key)
+            .chain(&self.decryption_keys)
+            .find(|key| key.id.as_bytes() == key_id)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The session state was encrypted with an unknown key: `{}`",
+                    String::from_utf8_lossy(key_id)
+                )
+            })?;
+
+        let plaintext = key
+            .cipher
+            .decrypt(
+                aes_gcm::Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt the session state"))?;
+
+        self.codec.decode(&plaintext)
+    }
+
+    fn json_fallback(&self) -> bool {
+        self.plaintext_fallback
+    }
+}
+
+/// The first byte of encoded session states whose values are stored as they are, rather than as
+/// JSON-encoded strings—the format used before [`SessionState`] held JSON values.
+///
+/// It is prepended to the output of the codec. Session states persisted in the previous format are
+/// always JSON-encoded, hence start with `{`.
+#[cfg(any(
+    feature = "cookie-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session"
+))]
+pub(crate) const TYPED_STATE_HEADER: u8 = b'~';
+
+/// Encodes session state using `codec`, marking it as holding typed values.
+#[cfg(any(
+    feature = "cookie-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session"
+))]
+pub(crate) fn encode_state(
+    codec: &dyn SessionStateCodec,
+    session_state: &SessionState,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut bytes = vec![TYPED_STATE_HEADER];
+    bytes.extend(codec.encode(session_state)?);
+    Ok(bytes)
+}
+
+/// Decodes session state using `codec`, falling back to JSON—the only encoding used before codecs
+/// became configurable—unless `codec` opts out of it.
+///
+/// A JSON-encoded session state always starts with `{`, while MessagePack and CBOR maps never do.
+///
+/// Values of session states persisted before they were stored as they are (i.e. lacking
+/// [`TYPED_STATE_HEADER`]) are JSON-encoded strings, which are decoded in place.
+#[cfg(any(
+    feature = "cookie-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session"
+))]
+pub(crate) fn decode_state(
+    codec: &dyn SessionStateCodec,
+    bytes: &[u8],
+) -> Result<SessionState, anyhow::Error> {
+    let (typed, bytes) = match bytes.split_first() {
+        Some((&TYPED_STATE_HEADER, bytes)) => (true, bytes),
+        _ => (false, bytes),
+    };
+
+    let mut session_state = match codec.decode(bytes) {
+        Ok(session_state) => session_state,
+        Err(err) if codec.json_fallback() && bytes.first() == Some(&b'{') => {
+            serde_json::from_slice(bytes).map_err(|_| err)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    if !typed {
+        for value in session_state.values_mut() {
+            if let serde_json::Value::String(encoded) = value {
+                if let Ok(decoded) = serde_json::from_str(encoded) {
+                    *value = decoded;
+                }
+            }
+        }
+    }
+
+    Ok(session_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn session_state() -> SessionState {
+        let mut session_state = HashMap::new();
+        session_state.insert("user_id".to_owned(), json!("ferris"));
+        session_state.insert("counter".to_owned(), json!(1));
+        session_state.insert("cart".to_owned(), json!({ "items": ["crab", "gear"] }));
+        session_state
+    }
+
+    /// The session state, as it was persisted before values were stored as they are.
+    fn legacy_session_state() -> HashMap<String, String> {
+        session_state()
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn json_codec_round_trip() {
+        let bytes = encode_state(&JsonCodec, &session_state()).unwrap();
+        assert_eq!(decode_state(&JsonCodec, &bytes).unwrap(), session_state());
+    }
+
+    #[test]
+    fn values_are_not_double_encoded() {
+        let bytes = encode_state(&JsonCodec, &session_state()).unwrap();
+        assert_eq!(bytes[0], TYPED_STATE_HEADER);
+        let encoded: serde_json::Value = serde_json::from_slice(&bytes[1..]).unwrap();
+        assert_eq!(encoded["cart"]["items"][0], "crab");
+        assert_eq!(encoded.as_object().unwrap().len(), session_state().len());
+    }
+
+    #[test]
+    fn legacy_session_state_is_decoded() {
+        let bytes = serde_json::to_vec(&legacy_session_state()).unwrap();
+        assert_eq!(decode_state(&JsonCodec, &bytes).unwrap(), session_state());
+
+        // strings that happen to be valid JSON are preserved in the current format
+        let mut session_state = HashMap::new();
+        session_state.insert("note".to_owned(), json!("42"));
+        let bytes = encode_state(&JsonCodec, &session_state).unwrap();
+        assert_eq!(decode_state(&JsonCodec, &bytes).unwrap(), session_state);
+    }
+
+    #[cfg(feature = "msgpack-codec")]
+    #[test]
+    fn msgpack_codec_round_trip_and_json_fallback() {
+        let bytes = encode_state(&MessagePackCodec, &session_state()).unwrap();
+        assert!(bytes.len() < encode_state(&JsonCodec, &session_state()).unwrap().len());
+        assert_eq!(
+            decode_state(&MessagePackCodec, &bytes).unwrap(),
+            session_state()
+        );
+
+        let legacy = serde_json::to_vec(&legacy_session_state()).unwrap();
+        assert_eq!(
+            decode_state(&MessagePackCodec, &legacy).unwrap(),
+            session_state()
+        );
+    }
+
+    #[cfg(feature = "cbor-codec")]
+    #[test]
+    fn cbor_codec_round_trip_and_json_fallback() {
+        let bytes = encode_state(&CborCodec, &session_state()).unwrap();
+        assert!(bytes.len() < encode_state(&JsonCodec, &session_state()).unwrap().len());
+        assert_eq!(decode_state(&CborCodec, &bytes).unwrap(), session_state());
+
+        let legacy = serde_json::to_vec(&legacy_session_state()).unwrap();
+        assert_eq!(decode_state(&CborCodec, &legacy).unwrap(), session_state());
+    }
+
+    #[cfg(feature = "encrypted-codec")]
+    #[test]
+    fn encrypted_codec_round_trip() {
+        let codec = EncryptedCodec::new("current", b"secret");
+        let bytes = encode_state(&codec, &session_state()).unwrap();
+        assert!(!String::from_utf8_lossy(&bytes).contains("ferris"));
+        assert_eq!(decode_state(&codec, &bytes).unwrap(), session_state());
+
+        // the same state encrypts differently every time
+        assert_ne!(bytes, encode_state(&codec, &session_state()).unwrap());
+    }
+
+    #[cfg(feature = "encrypted-codec")]
+    #[test]
+    fn encrypted_codec_supports_key_rotation() {
+        let previous = EncryptedCodec::new("previous", b"old-secret");
+        let bytes = encode_state(&previous, &session_state()).unwrap();
+
+        let rotated =
+            EncryptedCodec::new("current", b"new-secret").decryption_key("previous", b"old-secret");
+        assert_eq!(decode_state(&rotated, &bytes).unwrap(), session_state());
+
+        let retired = EncryptedCodec::new("current", b"new-secret");
+        assert!(decode_state(&retired, &bytes).is_err());
+
+        // the key ID is authenticated: it cannot be swapped for another one
+        let mut swapped = bytes.clone();
+        swapped[3..11].copy_from_slice(b"previuos");
+        let codec = rotated.decryption_key("previuos", b"old-secret");
+        assert!(decode_state(&codec, &swapped).is_err());
+    }
+
+    #[cfg(feature = "encrypted-codec")]
+    #[test]
+    fn encrypted_codec_rejects_tampered_states() {
+        let codec = EncryptedCodec::new("current", b"secret");
+        let mut bytes = encode_state(&codec, &session_state()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(decode_state(&codec, &bytes).is_err());
+
+        assert!(decode_state(&codec, &[1, 7, b'c']).is_err());
+        assert!(decode_state(&codec, b"").is_err());
+    }
+
+    #[cfg(feature = "encrypted-codec")]
+    #[test]
+    fn encrypted_codec_wraps_other_codecs() {
+        let codec = EncryptedCodec::new("current", b"secret").codec(JsonCodec);
+        let bytes = encode_state(&codec, &session_state()).unwrap();
+        assert_eq!(decode_state(&codec, &bytes).unwrap(), session_state());
+    }
+
+    #[cfg(feature = "encrypted-codec")]
+    #[test]
+    fn encrypted_codec_rejects_plaintext_states_unless_allowed() {
+        let plaintext = encode_state(&JsonCodec, &session_state()).unwrap();
+        let legacy = serde_json::to_vec(&legacy_session_state()).unwrap();
+
+        let codec = EncryptedCodec::new("current", b"secret");
+        assert!(decode_state(&codec, &plaintext).is_err());
+        assert!(decode_state(&codec, &legacy).is_err());
+
+        let codec = codec.plaintext_fallback(true);
+        assert_eq!(decode_state(&codec, &plaintext).unwrap(), session_state());
+        assert_eq!(decode_state(&codec, &legacy).unwrap(), session_state());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(decode_state(&JsonCodec, b"random-thing-which-is-not-json").is_err());
+        assert!(decode_state(&JsonCodec, b"{not-json").is_err());
+    }
+}
@@ -1,10 +1,11 @@
-use std::convert::TryInto;
+use std::sync::Arc;
 
 use actix_web::cookie::time::Duration;
 use anyhow::Error;
 
 use super::SessionKey;
 use crate::storage::{
+    codec::{decode_state, encode_state, JsonCodec, SessionStateCodec, TYPED_STATE_HEADER},
     interface::{LoadError, SaveError, SessionState, UpdateError},
     SessionStore,
 };
@@ -37,7 +38,8 @@ use crate::storage::{
 /// # Limitations
 /// Cookies are subject to size limits so we require session keys to be shorter than 4096 bytes.
 /// This translates into a limit on the maximum size of the session state when using cookies as
-/// storage backend.
+/// storage backend. You can lift it by allowing [`SessionMiddleware`] to split the session state
+/// across multiple cookies—see [`SessionMiddlewareBuilder::cookie_max_chunks`].
 ///
 /// The session cookie can always be inspected by end users via the developer tools exposed by their
 /// browsers. We strongly recommend setting the policy to [`CookieContentSecurity::Private`] when
@@ -46,18 +48,175 @@ use crate::storage::{
 /// There is no way to invalidate a session before its natural expiry when using cookies as the
 /// storage backend.
 ///
+/// # Encoding
+/// The session state is encoded as JSON by default. You can squeeze more state into the cookie
+/// by switching to a more compact [`SessionStateCodec`] using [`CookieSessionStore::builder`]—the
+/// output of binary codecs is stored base64-encoded.
+///
+/// ```
+/// use actix_session::storage::{CookieSessionStore, JsonCodec};
+///
+/// let store = CookieSessionStore::builder().codec(JsonCodec).build();
+/// ```
+///
+/// # Compression
+/// Enable the `cookie-compression` feature flag to compress (using deflate) encoded session states
+/// that exceed a size threshold—see [`CookieSessionStoreBuilder::compression_threshold`].
+/// Compression is performed by the store, therefore before the session cookie is signed or
+/// encrypted by [`SessionMiddleware`]. Cookies that were never compressed keep loading as before.
+///
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+/// [`SessionMiddlewareBuilder::cookie_max_chunks`]: crate::config::SessionMiddlewareBuilder::cookie_max_chunks
 /// [`CookieContentSecurity::Private`]: crate::config::CookieContentSecurity::Private
 #[cfg_attr(docsrs, doc(cfg(feature = "cookie-session")))]
-#[derive(Default)]
-#[non_exhaustive]
-pub struct CookieSessionStore;
+#[derive(Clone)]
+pub struct CookieSessionStore {
+    codec: Arc<dyn SessionStateCodec>,
+    #[cfg(feature = "cookie-compression")]
+    compression_threshold: Option<usize>,
+}
+
+impl CookieSessionStore {
+    /// A fluent API to configure [`CookieSessionStore`].
+    pub fn builder() -> CookieSessionStoreBuilder {
+        CookieSessionStoreBuilder {
+            codec: Arc::new(JsonCodec),
+            #[cfg(feature = "cookie-compression")]
+            compression_threshold: None,
+        }
+    }
+
+    /// Turns the encoded session state into the value of the session cookie.
+    ///
+    /// JSON is stored as is (after the [`TYPED_STATE_HEADER`]), the output of binary codecs is
+    /// base64-encoded. Compressed states are base64-encoded and prefixed with
+    /// [`COMPRESSION_MARKER`].
+    fn to_cookie_value(&self, bytes: Vec<u8>) -> Result<String, anyhow::Error> {
+        #[cfg(feature = "cookie-compression")]
+        if let Some(threshold) = self.compression_threshold {
+            if bytes.len() > threshold {
+                let compressed = compress(&bytes)?;
+
+                // base64 inflates its input by a third—only keep the compressed state if it
+                // actually results in a smaller cookie.
+                if compressed.len() * 4 / 3 + 1 < bytes.len() {
+                    let mut value = String::from(COMPRESSION_MARKER);
+                    base64::encode_config_buf(compressed, base64::URL_SAFE_NO_PAD, &mut value);
+                    return Ok(value);
+                }
+            }
+        }
+
+        if let [TYPED_STATE_HEADER, b'{', ..] = bytes.as_slice() {
+            Ok(String::from_utf8(bytes)?)
+        } else {
+            Ok(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+        }
+    }
+
+    /// Extracts the encoded session state from the value of the session cookie.
+    fn from_cookie_value(value: &str) -> Result<Vec<u8>, anyhow::Error> {
+        // JSON, either typed or persisted in the previous format
+        if value.starts_with(char::from(TYPED_STATE_HEADER)) || value.starts_with('{') {
+            return Ok(value.as_bytes().to_vec());
+        }
+
+        if let Some(compressed) = value.strip_prefix(COMPRESSION_MARKER) {
+            let compressed = base64::decode_config(compressed, base64::URL_SAFE_NO_PAD)?;
+            return decompress(&compressed);
+        }
+
+        Ok(base64::decode_config(value, base64::URL_SAFE_NO_PAD)?)
+    }
+}
+
+/// Prefix of compressed session states. It can be told apart from both JSON and base64-encoded
+/// session states, since neither can start with a `.`.
+const COMPRESSION_MARKER: char = '.';
+
+#[cfg(feature = "cookie-compression")]
+fn compress(bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    use std::io::Write as _;
+
+    let mut encoder =
+        flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(feature = "cookie-compression")]
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    use std::io::Read as _;
+
+    let mut decompressed = Vec::new();
+    flate2::read::DeflateDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "cookie-compression"))]
+fn decompress(_bytes: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    Err(anyhow::anyhow!(
+        "The session state is compressed, but the `cookie-compression` feature flag is not enabled"
+    ))
+}
+
+impl Default for CookieSessionStore {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// A fluent builder to construct a [`CookieSessionStore`] instance with custom configuration
+/// parameters.
+#[cfg_attr(docsrs, doc(cfg(feature = "cookie-session")))]
+#[must_use]
+pub struct CookieSessionStoreBuilder {
+    codec: Arc<dyn SessionStateCodec>,
+    #[cfg(feature = "cookie-compression")]
+    compression_threshold: Option<usize>,
+}
+
+impl CookieSessionStoreBuilder {
+    /// Set the codec used to encode the session state stored in the cookie.
+    ///
+    /// Defaults to [`JsonCodec`].
+    pub fn codec<C: SessionStateCodec + 'static>(mut self, codec: C) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Compress the encoded session state when it is larger than `threshold` bytes.
+    ///
+    /// Compression is skipped if it would not shrink the session cookie—e.g. for session states
+    /// made of random-looking data.
+    ///
+    /// By default, the session state is never compressed.
+    #[cfg(feature = "cookie-compression")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "cookie-compression")))]
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+
+    /// Finalise the builder and return a [`CookieSessionStore`] instance.
+    #[must_use]
+    pub fn build(self) -> CookieSessionStore {
+        CookieSessionStore {
+            codec: self.codec,
+            #[cfg(feature = "cookie-compression")]
+            compression_threshold: self.compression_threshold,
+        }
+    }
+}
 
 #[async_trait::async_trait(?Send)]
 impl SessionStore for CookieSessionStore {
     async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
-        serde_json::from_str(session_key.as_ref())
+        let bytes =
+            Self::from_cookie_value(session_key.as_ref()).map_err(LoadError::Deserialization)?;
+
+        decode_state(self.codec.as_ref(), &bytes)
             .map(Some)
-            .map_err(anyhow::Error::new)
             .map_err(LoadError::Deserialization)
     }
 
@@ -66,14 +225,16 @@ impl SessionStore for CookieSessionStore {
         session_state: SessionState,
         _ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
-        let session_key = serde_json::to_string(&session_state)
-            .map_err(anyhow::Error::new)
+        let bytes =
+            encode_state(self.codec.as_ref(), &session_state).map_err(SaveError::Serialization)?;
+
+        let session_key = self
+            .to_cookie_value(bytes)
             .map_err(SaveError::Serialization)?;
 
-        Ok(session_key
-            .try_into()
-            .map_err(Into::into)
-            .map_err(SaveError::Other)?)
+        // `SessionMiddleware` checks that the session key fits in the cookie(s) it is allowed to
+        // use, since it is the one in charge of splitting it.
+        Ok(SessionKey::new_unbounded(session_key))
     }
 
     async fn update(
@@ -109,6 +270,96 @@ mod tests {
         acceptance_test_suite(CookieSessionStore::default, false).await;
     }
 
+    #[cfg(feature = "msgpack-codec")]
+    #[actix_web::test]
+    async fn test_session_workflow_with_binary_codec() {
+        use crate::storage::MessagePackCodec;
+
+        acceptance_test_suite(
+            || {
+                CookieSessionStore::builder()
+                    .codec(MessagePackCodec)
+                    .build()
+            },
+            false,
+        )
+        .await;
+    }
+
+    #[cfg(feature = "msgpack-codec")]
+    #[actix_web::test]
+    async fn json_session_state_is_loaded_after_switching_codec() {
+        use std::collections::HashMap;
+
+        use crate::storage::MessagePackCodec;
+
+        let mut session_state = HashMap::new();
+        session_state.insert("counter".to_owned(), serde_json::json!(1));
+
+        let session_key = CookieSessionStore::default()
+            .save(session_state.clone(), &Duration::days(1))
+            .await
+            .unwrap();
+        assert!(session_key.as_ref().starts_with("~{"));
+
+        let store = CookieSessionStore::builder()
+            .codec(MessagePackCodec)
+            .build();
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(session_state));
+    }
+
+    #[cfg(feature = "cookie-compression")]
+    #[actix_web::test]
+    async fn test_session_workflow_with_compression() {
+        acceptance_test_suite(
+            || {
+                CookieSessionStore::builder()
+                    .compression_threshold(0)
+                    .build()
+            },
+            false,
+        )
+        .await;
+    }
+
+    #[cfg(feature = "cookie-compression")]
+    #[actix_web::test]
+    async fn large_session_states_are_compressed() {
+        use std::collections::HashMap;
+
+        let store = CookieSessionStore::builder()
+            .compression_threshold(256)
+            .build();
+
+        let mut session_state = HashMap::new();
+        session_state.insert("cart".to_owned(), serde_json::json!(vec!["item"; 1_000]));
+
+        // too large to fit in a cookie without compression
+        let session_key = CookieSessionStore::default()
+            .save(session_state.clone(), &Duration::days(1))
+            .await
+            .unwrap();
+        assert!(session_key.as_ref().len() > 4064);
+
+        let session_key = store
+            .save(session_state.clone(), &Duration::days(1))
+            .await
+            .unwrap();
+        assert!(session_key.as_ref().starts_with(COMPRESSION_MARKER));
+        assert_eq!(store.load(&session_key).await.unwrap(), Some(session_state));
+
+        // small states are left uncompressed
+        let session_key = store
+            .save(HashMap::new(), &Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(session_key.as_ref(), "~{}");
+        assert_eq!(
+            store.load(&session_key).await.unwrap(),
+            Some(HashMap::new())
+        );
+    }
+
     #[actix_web::test]
     async fn loading_a_random_session_key_returns_deserialization_error() {
         let store = CookieSessionStore::default();
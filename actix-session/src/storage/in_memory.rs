@@ -0,0 +1,506 @@
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+    thread,
+    time::Instant,
+};
+
+use actix_web::cookie::time::Duration;
+use anyhow::Error;
+
+use super::SessionKey;
+use crate::storage::{
+    interface::{
+        LoadError, SaveError, SessionState, SessionVersion, UpdateError, VersionedSessionState,
+    },
+    lru::LruMap,
+    utils::{generate_session_key, missing_subject_key, session_subject},
+    IndexedSessionStore, SessionStore,
+};
+
+/// Keep session state in the memory of the current process.
+///
+/// ```no_run
+/// use actix_web::{cookie::Key, web, App, HttpServer, HttpResponse, Error};
+/// use actix_session::{SessionMiddleware, storage::InMemorySessionStore};
+///
+/// // The secret key would usually be read from a configuration file/environment variables.
+/// fn get_secret_key() -> Key {
+///     # todo!()
+///     // [...]
+/// }
+///
+/// #[actix_web::main]
+/// async fn main() -> std::io::Result<()> {
+///     let secret_key = get_secret_key();
+///     let store = InMemorySessionStore::new();
+///
+///     HttpServer::new(move ||
+///             App::new()
+///             .wrap(SessionMiddleware::new(store.clone(), secret_key.clone()))
+///             .default_service(web::to(|| HttpResponse::Ok())))
+///         .bind(("127.0.0.1", 8080))?
+///         .run()
+///         .await
+/// }
+/// ```
+///
+/// Clones of an [`InMemorySessionStore`] share the same underlying storage—create the store once,
+/// outside of the `HttpServer::new` closure, and pass a clone to each worker.
+///
+/// # Expiration and eviction
+/// Session states are never returned after their TTL has elapsed. Expired states are removed
+/// from memory by a background thread, at the interval configured via
+/// [`InMemorySessionStoreBuilder::cleanup_interval`].
+///
+/// The store holds at most [`max_capacity`](InMemorySessionStoreBuilder::max_capacity) session
+/// states. When a new session is created while the store is full, expired session states are
+/// removed first; if none has expired, the least recently used session state is evicted—the user it
+/// belonged to will be handed a fresh session on their next request.
+///
+/// # Sessions by subject
+/// [`InMemorySessionStore`] implements [`IndexedSessionStore`]: configure the session state key
+/// holding the subject of a session via [`InMemorySessionStoreBuilder::subject_key`].
+///
+/// # Limitations
+/// Session states are lost when the process exits and they are not shared across multiple
+/// instances of your application. Use [`RedisSessionStore`] or [`RedisActorSessionStore`] if you
+/// are running more than one replica.
+///
+/// [`RedisSessionStore`]: crate::storage::RedisSessionStore
+/// [`RedisActorSessionStore`]: crate::storage::RedisActorSessionStore
+#[cfg_attr(docsrs, doc(cfg(feature = "in-memory-session")))]
+#[derive(Clone)]
+pub struct InMemorySessionStore {
+    entries: Arc<Mutex<Entries>>,
+    subject_key: Option<Arc<str>>,
+}
+
+impl InMemorySessionStore {
+    /// A fluent API to configure [`InMemorySessionStore`].
+    pub fn builder() -> InMemorySessionStoreBuilder {
+        InMemorySessionStoreBuilder {
+            max_capacity: default_max_capacity(),
+            cleanup_interval: default_cleanup_interval(),
+            subject_key: None,
+        }
+    }
+
+    /// Create a new instance of [`InMemorySessionStore`] using the default configuration.
+    pub fn new() -> InMemorySessionStore {
+        Self::builder().build()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        // The map is left in a consistent state even if a thread panicked while holding the lock.
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for InMemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const fn default_max_capacity() -> usize {
+    10_000
+}
+
+const fn default_cleanup_interval() -> Duration {
+    Duration::minutes(1)
+}
+
+/// A fluent builder to construct an [`InMemorySessionStore`] instance with custom configuration
+/// parameters.
+#[cfg_attr(docsrs, doc(cfg(feature = "in-memory-session")))]
+#[must_use]
+pub struct InMemorySessionStoreBuilder {
+    max_capacity: usize,
+    cleanup_interval: Duration,
+    subject_key: Option<Arc<str>>,
+}
+
+impl InMemorySessionStoreBuilder {
+    /// Set the maximum number of session states kept in memory.
+    ///
+    /// When a new session is created while the store is full, expired session states are removed
+    /// first; if none has expired, the least recently used session state is evicted.
+    ///
+    /// Defaults to 10,000.
+    ///
+    /// # Panics
+    /// Panics if `max_capacity` is `0`.
+    pub fn max_capacity(mut self, max_capacity: usize) -> Self {
+        assert!(
+            max_capacity > 0,
+            "The in-memory session store must be able to hold at least one session state"
+        );
+        self.max_capacity = max_capacity;
+        self
+    }
+
+    /// Set how often expired session states should be removed from memory.
+    ///
+    /// Expired session states are never returned by the store, regardless of this interval—it only
+    /// controls when the memory they occupy is reclaimed.
+    ///
+    /// Defaults to 1 minute.
+    ///
+    /// # Panics
+    /// Panics if `cleanup_interval` is not positive.
+    pub fn cleanup_interval(mut self, cleanup_interval: Duration) -> Self {
+        assert!(
+            cleanup_interval.is_positive(),
+            "The interval between two removals of expired session states must be positive"
+        );
+        self.cleanup_interval = cleanup_interval;
+        self
+    }
+
+    /// Set the session state key holding the subject (e.g. the user ID) a session belongs to.
+    ///
+    /// It is required to look up sessions by subject—see [`IndexedSessionStore`].
+    pub fn subject_key<S: Into<String>>(mut self, subject_key: S) -> Self {
+        self.subject_key = Some(subject_key.into().into());
+        self
+    }
+
+    /// Finalise the builder and return an [`InMemorySessionStore`] instance.
+    ///
+    /// A background thread is spawned to remove expired session states. It stops on its own once
+    /// the store and all its clones have been dropped.
+    #[must_use]
+    pub fn build(self) -> InMemorySessionStore {
+        let entries = Arc::new(Mutex::new(LruMap::new(self.max_capacity)));
+        spawn_cleanup_thread(
+            Arc::downgrade(&entries),
+            to_std_duration(&self.cleanup_interval),
+        );
+
+        InMemorySessionStore {
+            entries,
+            subject_key: self.subject_key,
+        }
+    }
+}
+
+fn spawn_cleanup_thread(entries: Weak<Mutex<Entries>>, interval: std::time::Duration) {
+    thread::Builder::new()
+        .name("actix-session-cleanup".to_owned())
+        .spawn(move || loop {
+            thread::sleep(interval);
+
+            match entries.upgrade() {
+                Some(entries) => entries
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .purge_expired(Instant::now()),
+                None => break,
+            }
+        })
+        .expect("Failed to spawn the thread removing expired session states");
+}
+
+/// Negative durations are treated as zero—the state is already expired.
+fn to_std_duration(duration: &Duration) -> std::time::Duration {
+    std::time::Duration::try_from(*duration).unwrap_or_default()
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        Ok(self
+            .entries()
+            .get(session_key.as_ref(), Instant::now())
+            .map(|entry| entry.value.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let expires_at = Instant::now() + to_std_duration(ttl);
+
+        self.entries().insert(
+            session_key.as_ref().to_owned(),
+            session_state,
+            expires_at,
+            Instant::now(),
+        );
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let now = Instant::now();
+
+        let session_state = {
+            let mut entries = self.entries();
+
+            if entries.get(session_key.as_ref(), now).is_some() {
+                entries.insert(
+                    session_key.as_ref().to_owned(),
+                    session_state,
+                    now + to_std_duration(ttl),
+                    now,
+                );
+
+                return Ok(session_key);
+            }
+
+            session_state
+        };
+
+        // The session state expired (or was evicted) between the load operation and the update
+        // operation. We fall back to the `save` routine to ensure that the new key is unique.
+        self.save(session_state, ttl)
+            .await
+            .map_err(|err| match err {
+                SaveError::Serialization(err) => UpdateError::Serialization(err),
+                SaveError::Other(err) => UpdateError::Other(err),
+            })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), Error> {
+        let now = Instant::now();
+        self.entries()
+            .set_expiry(session_key.as_ref(), now + to_std_duration(ttl), now);
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.entries().remove(session_key.as_ref());
+        Ok(())
+    }
+
+    async fn load_versioned(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<VersionedSessionState>, LoadError> {
+        Ok(self
+            .entries()
+            .get(session_key.as_ref(), Instant::now())
+            .map(|entry| VersionedSessionState {
+                state: entry.value.clone(),
+                version: Some(SessionVersion::new(entry.revision.to_string())),
+            }))
+    }
+
+    async fn update_versioned(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        version: &SessionVersion,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let now = Instant::now();
+
+        {
+            let mut entries = self.entries();
+
+            if let Some(entry) = entries.get(session_key.as_ref(), now) {
+                if entry.revision.to_string() != version.as_ref() {
+                    return Err(UpdateError::Conflict);
+                }
+
+                entries.insert(
+                    session_key.as_ref().to_owned(),
+                    session_state,
+                    now + to_std_duration(ttl),
+                    now,
+                );
+
+                return Ok(session_key);
+            }
+        }
+
+        // The session state expired (or was evicted) since it was loaded: there is nothing left to
+        // conflict with.
+        self.update(session_key, session_state, ttl).await
+    }
+}
+
+/// Session states are looked up by scanning the whole store, which holds at most
+/// [`max_capacity`](InMemorySessionStoreBuilder::max_capacity) states.
+#[async_trait::async_trait(?Send)]
+impl IndexedSessionStore for InMemorySessionStore {
+    async fn sessions_for(&self, subject: &str) -> Result<Vec<SessionKey>, Error> {
+        let subject_key = self
+            .subject_key
+            .as_deref()
+            .ok_or_else(missing_subject_key)?;
+
+        Ok(
+            keys_for(&self.entries(), subject_key, subject, Instant::now())
+                .into_iter()
+                .map(SessionKey::new_unbounded)
+                .collect(),
+        )
+    }
+
+    async fn delete_all_for(&self, subject: &str) -> Result<usize, Error> {
+        let subject_key = self
+            .subject_key
+            .as_deref()
+            .ok_or_else(missing_subject_key)?;
+
+        let mut entries = self.entries();
+        let keys = keys_for(&entries, subject_key, subject, Instant::now());
+        for key in &keys {
+            entries.remove(key);
+        }
+
+        Ok(keys.len())
+    }
+}
+
+/// Session states indexed by session key, with expiry and least-recently-used bookkeeping.
+///
+/// The revision of an entry is used as the version of its session state.
+type Entries = LruMap<SessionState>;
+
+/// Returns the keys of the live session states whose subject is `subject`.
+fn keys_for(entries: &Entries, subject_key: &str, subject: &str, now: Instant) -> Vec<String> {
+    entries
+        .iter(now)
+        .filter(|(_, state)| session_subject(state, subject_key).as_deref() == Some(subject))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::cookie::time;
+
+    use super::*;
+    use crate::test_helpers::{
+        acceptance_test_suite, optimistic_concurrency_test_suite, subject_index_test_suite,
+    };
+
+    #[actix_web::test]
+    async fn test_session_workflow() {
+        let store = InMemorySessionStore::new();
+        acceptance_test_suite(move || store.clone(), true).await;
+    }
+
+    #[actix_web::test]
+    async fn loading_a_missing_session_returns_none() {
+        let store = InMemorySessionStore::new();
+        let session_key = generate_session_key();
+        assert!(store.load(&session_key).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn loading_an_expired_session_returns_none() {
+        let store = InMemorySessionStore::new();
+        let session_key = store
+            .save(HashMap::new(), &time::Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(store.load(&session_key).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn updating_of_an_expired_state_is_handled_gracefully() {
+        let store = InMemorySessionStore::new();
+        let session_key = generate_session_key();
+        let initial_session_key = session_key.as_ref().to_owned();
+        let updated_session_key = store
+            .update(session_key, HashMap::new(), &time::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_ne!(initial_session_key, updated_session_key.as_ref());
+    }
+
+    #[actix_web::test]
+    async fn least_recently_used_state_is_evicted_when_full() {
+        let store = InMemorySessionStore::builder().max_capacity(2).build();
+        let ttl = time::Duration::minutes(1);
+
+        let first = store.save(HashMap::new(), &ttl).await.unwrap();
+        let second = store.save(HashMap::new(), &ttl).await.unwrap();
+
+        // touch the first session, making the second one the least recently used
+        store.load(&first).await.unwrap();
+        let third = store.save(HashMap::new(), &ttl).await.unwrap();
+
+        assert!(store.load(&first).await.unwrap().is_some());
+        assert!(store.load(&second).await.unwrap().is_none());
+        assert!(store.load(&third).await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn expired_states_are_evicted_before_live_ones() {
+        let store = InMemorySessionStore::builder().max_capacity(2).build();
+        let ttl = time::Duration::minutes(1);
+
+        let live = store.save(HashMap::new(), &ttl).await.unwrap();
+        store
+            .save(HashMap::new(), &time::Duration::ZERO)
+            .await
+            .unwrap();
+
+        // the live session is the least recently used one, but the other one has expired
+        let new = store.save(HashMap::new(), &ttl).await.unwrap();
+
+        assert!(store.load(&live).await.unwrap().is_some());
+        assert!(store.load(&new).await.unwrap().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one session state")]
+    fn max_capacity_must_not_be_zero() {
+        let _ = InMemorySessionStore::builder().max_capacity(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn cleanup_interval_must_be_positive() {
+        let _ = InMemorySessionStore::builder().cleanup_interval(time::Duration::ZERO);
+    }
+
+    #[actix_web::test]
+    async fn expired_states_are_removed_in_the_background() {
+        let store = InMemorySessionStore::builder()
+            .cleanup_interval(time::Duration::milliseconds(10))
+            .build();
+        store
+            .save(HashMap::new(), &time::Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(store.entries().len(), 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(store.entries().len() == 0);
+    }
+
+    #[actix_web::test]
+    async fn sessions_can_be_looked_up_and_deleted_by_subject() {
+        let store = InMemorySessionStore::builder()
+            .subject_key("user_id")
+            .build();
+        subject_index_test_suite(store).await;
+    }
+
+    #[actix_web::test]
+    async fn looking_up_sessions_by_subject_requires_a_subject_key() {
+        let store = InMemorySessionStore::new();
+        assert!(store.sessions_for("ferris").await.is_err());
+        assert!(store.delete_all_for("ferris").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn concurrent_updates_are_detected() {
+        optimistic_concurrency_test_suite(InMemorySessionStore::new()).await;
+    }
+}
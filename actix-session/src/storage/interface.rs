@@ -5,7 +5,10 @@ use derive_more::Display;
 
 use super::SessionKey;
 
-pub(crate) type SessionState = HashMap<String, String>;
+/// The state attached to a session: a map from keys to arbitrary JSON values.
+///
+/// Storage backends are handed the session state as is—values are not encoded as JSON strings.
+pub type SessionState = HashMap<String, serde_json::Value>;
 
 /// The interface to retrieve and save the current session data from/to the chosen storage backend.
 ///
@@ -45,6 +48,91 @@ pub trait SessionStore {
 
     /// Deletes a session from the store.
     async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error>;
+
+    /// Loads the session state associated to a session key, alongside its version.
+    ///
+    /// Stores supporting optimistic concurrency control return the version of the session state,
+    /// to be passed to [`SessionStore::update_versioned`]. The default implementation does not
+    /// return a version—concurrent updates of the same session state are never detected.
+    async fn load_versioned(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<VersionedSessionState>, LoadError> {
+        Ok(self
+            .load(session_key)
+            .await?
+            .map(|state| VersionedSessionState {
+                state,
+                version: None,
+            }))
+    }
+
+    /// Updates the session state associated to a pre-existing session key, as long as it has not
+    /// been modified since `version` was loaded.
+    ///
+    /// It returns [`UpdateError::Conflict`] if the session state has been modified in the
+    /// meantime. The default implementation ignores `version` and performs an
+    /// [`update`](SessionStore::update).
+    async fn update_versioned(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        version: &SessionVersion,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let _ = version;
+        self.update(session_key, session_state, ttl).await
+    }
+}
+
+/// An opaque identifier of a revision of a session state, used to detect concurrent updates.
+///
+/// See [`SessionStore::load_versioned`] and [`SessionStore::update_versioned`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionVersion(String);
+
+impl SessionVersion {
+    /// Creates a session version from any value identifying a revision of a session state (e.g.
+    /// a counter or a hash of the stored session state).
+    pub fn new(version: impl Into<String>) -> Self {
+        Self(version.into())
+    }
+}
+
+impl AsRef<str> for SessionVersion {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A session state, as returned by [`SessionStore::load_versioned`].
+#[derive(Debug, Clone)]
+pub struct VersionedSessionState {
+    /// The session state.
+    pub state: SessionState,
+
+    /// The version of the session state, if the store supports optimistic concurrency control.
+    pub version: Option<SessionVersion>,
+}
+
+/// An optional capability of session stores: finding and deleting all the sessions belonging to
+/// the same subject (e.g. a user), to implement "log out everywhere" or account lock-out flows.
+///
+/// The subject of a session is read from its state—stores implementing this trait let you pick
+/// the key holding it (e.g. `user_id`) via their builder. String and numeric values are supported.
+/// Sessions whose state does not hold a subject are not indexed.
+///
+/// [`async-trait`](https://docs.rs/async-trait) is used for this trait's definition, just like for
+/// [`SessionStore`].
+#[async_trait::async_trait(?Send)]
+pub trait IndexedSessionStore: SessionStore {
+    /// Returns the keys of all the live sessions belonging to `subject`.
+    async fn sessions_for(&self, subject: &str) -> Result<Vec<SessionKey>, anyhow::Error>;
+
+    /// Deletes all the sessions belonging to `subject`.
+    ///
+    /// Returns the number of deleted sessions.
+    async fn delete_all_for(&self, subject: &str) -> Result<usize, anyhow::Error>;
 }
 
 // We cannot derive the `Error` implementation using `derive_more` for our custom errors:
@@ -95,6 +183,7 @@ impl std::error::Error for SaveError {
 
 #[derive(Debug, Display)]
 /// Possible failures modes for [`SessionStore::update`].
+#[non_exhaustive]
 pub enum UpdateError {
     /// Failed to serialize session state.
     #[display(fmt = "Failed to serialize session state")]
@@ -103,6 +192,12 @@ pub enum UpdateError {
     /// Something went wrong when updating the session state.
     #[display(fmt = "Something went wrong when updating the session state.")]
     Other(anyhow::Error),
+
+    /// The session state has been modified since it was loaded.
+    ///
+    /// Only returned by [`SessionStore::update_versioned`].
+    #[display(fmt = "The session state has been modified concurrently.")]
+    Conflict,
 }
 
 impl std::error::Error for UpdateError {
@@ -110,6 +205,7 @@ impl std::error::Error for UpdateError {
         match self {
             Self::Serialization(err) => Some(err.as_ref()),
             Self::Other(err) => Some(err.as_ref()),
+            Self::Conflict => None,
         }
     }
 }
@@ -0,0 +1,215 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+/// A bounded map from session keys to values with a time-to-live, evicting the least recently
+/// used entry when full.
+///
+/// Entries are indexed both by last access and by expiry: making room for a new entry removes
+/// the expired entries first, without scanning the whole map.
+pub(crate) struct LruMap<V> {
+    entries: HashMap<String, Slot<V>>,
+    /// Keys ordered by last access, oldest first.
+    recency: BTreeMap<u64, String>,
+    /// Keys ordered by expiry, soonest first. Ties are broken by revision.
+    expiry: BTreeMap<(Instant, u64), String>,
+    /// Monotonic counter used to order accesses.
+    clock: u64,
+    max_capacity: usize,
+}
+
+pub(crate) struct Slot<V> {
+    pub(crate) value: V,
+    /// The clock value when the entry was inserted, unique across entries.
+    pub(crate) revision: u64,
+    expires_at: Instant,
+    last_access: u64,
+}
+
+impl<V> LruMap<V> {
+    pub(crate) fn new(max_capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            expiry: BTreeMap::new(),
+            clock: 0,
+            max_capacity,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Returns the entry attached to `key`, if it has not expired, and marks it as recently used.
+    pub(crate) fn get(&mut self, key: &str, now: Instant) -> Option<&Slot<V>> {
+        match self.entries.get(key) {
+            Some(slot) if slot.expires_at <= now => {
+                self.remove(key);
+                None
+            }
+            Some(_) => {
+                let access = self.tick();
+                let slot = self.entries.get_mut(key)?;
+                self.recency.remove(&slot.last_access);
+                self.recency.insert(access, key.to_owned());
+                slot.last_access = access;
+                Some(slot)
+            }
+            None => None,
+        }
+    }
+
+    /// Inserts `value`, replacing the entry attached to `key` if any.
+    ///
+    /// If the map is full, expired entries are removed first; if none has expired, the least
+    /// recently used entries are evicted.
+    pub(crate) fn insert(&mut self, key: String, value: V, expires_at: Instant, now: Instant) {
+        self.remove(&key);
+
+        if self.entries.len() >= self.max_capacity {
+            self.purge_expired(now);
+        }
+
+        while !self.entries.is_empty() && self.entries.len() >= self.max_capacity {
+            self.evict_least_recently_used();
+        }
+
+        let access = self.tick();
+        self.recency.insert(access, key.clone());
+        self.expiry.insert((expires_at, access), key.clone());
+        self.entries.insert(
+            key,
+            Slot {
+                value,
+                revision: access,
+                expires_at,
+                last_access: access,
+            },
+        );
+    }
+
+    /// Changes the expiry of the entry attached to `key`, if it has not expired yet.
+    #[cfg(feature = "in-memory-session")]
+    pub(crate) fn set_expiry(&mut self, key: &str, expires_at: Instant, now: Instant) {
+        if let Some(slot) = self.entries.get_mut(key) {
+            if slot.expires_at > now {
+                let key = self
+                    .expiry
+                    .remove(&(slot.expires_at, slot.revision))
+                    .unwrap_or_else(|| key.to_owned());
+                self.expiry.insert((expires_at, slot.revision), key);
+                slot.expires_at = expires_at;
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.recency.remove(&slot.last_access);
+        self.expiry.remove(&(slot.expires_at, slot.revision));
+        Some(slot.value)
+    }
+
+    #[cfg(feature = "redis-rs-session")]
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.expiry.clear();
+    }
+
+    /// Iterates over the entries that have not expired.
+    #[cfg(feature = "in-memory-session")]
+    pub(crate) fn iter(&self, now: Instant) -> impl Iterator<Item = (&String, &V)> {
+        self.entries
+            .iter()
+            .filter(move |(_, slot)| slot.expires_at > now)
+            .map(|(key, slot)| (key, &slot.value))
+    }
+
+    /// Removes the expired entries, soonest expiry first.
+    pub(crate) fn purge_expired(&mut self, now: Instant) {
+        while let Some(entry) = self.expiry.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+
+            let key = entry.remove();
+            if let Some(slot) = self.entries.remove(&key) {
+                self.recency.remove(&slot.last_access);
+            }
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.recency.values().next().cloned();
+
+        if let Some(key) = oldest {
+            tracing::debug!(
+                "The session state map is full, evicting its least recently used entry."
+            );
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "in-memory-session")]
+    fn expired_entries_are_purged_in_expiry_order() {
+        let now = Instant::now();
+        let mut map = LruMap::new(10);
+        map.insert("soon".to_owned(), (), now + Duration::from_secs(1), now);
+        map.insert("later".to_owned(), (), now + Duration::from_secs(3), now);
+        map.insert("extended".to_owned(), (), now + Duration::from_secs(1), now);
+        map.set_expiry("extended", now + Duration::from_secs(5), now);
+
+        map.purge_expired(now + Duration::from_secs(2));
+        assert_eq!(map.len(), 2);
+        assert!(map.get("soon", now).is_none());
+
+        map.purge_expired(now + Duration::from_secs(4));
+        assert_eq!(map.len(), 1);
+        assert!(map.get("extended", now).is_some());
+    }
+
+    #[test]
+    fn expired_entries_make_room_before_live_ones_are_evicted() {
+        let now = Instant::now();
+        let mut map = LruMap::new(2);
+        map.insert("live".to_owned(), (), now + Duration::from_secs(60), now);
+        map.insert("expired".to_owned(), (), now, now);
+        map.insert("new".to_owned(), (), now + Duration::from_secs(60), now);
+
+        assert_eq!(map.len(), 2);
+        assert!(map.get("live", now).is_some());
+        assert!(map.get("new", now).is_some());
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted_when_full() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let mut map = LruMap::new(2);
+        map.insert("first".to_owned(), (), now + ttl, now);
+        map.insert("second".to_owned(), (), now + ttl, now);
+        map.get("first", now);
+        map.insert("third".to_owned(), (), now + ttl, now);
+
+        assert!(map.get("first", now).is_some());
+        assert!(map.get("second", now).is_none());
+        assert!(map.get("third", now).is_some());
+    }
+}
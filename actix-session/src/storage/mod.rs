@@ -1,26 +1,62 @@
 //! Pluggable storage backends for session state.
 
+mod cached;
+mod codec;
 mod interface;
+mod lru;
 mod session_key;
 
-pub use self::interface::{LoadError, SaveError, SessionStore, UpdateError};
+pub use self::cached::{CachedSessionStore, CachedSessionStoreBuilder};
+#[cfg(feature = "cbor-codec")]
+pub use self::codec::CborCodec;
+#[cfg(feature = "encrypted-codec")]
+pub use self::codec::EncryptedCodec;
+#[cfg(feature = "msgpack-codec")]
+pub use self::codec::MessagePackCodec;
+pub use self::codec::{JsonCodec, SessionStateCodec};
+pub use self::interface::{
+    IndexedSessionStore, LoadError, SaveError, SessionState, SessionStore, SessionVersion,
+    UpdateError, VersionedSessionState,
+};
 pub use self::session_key::SessionKey;
+pub(crate) use self::session_key::MAX_COOKIE_CONTENT_LENGTH;
 
 #[cfg(feature = "cookie-session")]
 mod cookie;
 
+#[cfg(feature = "in-memory-session")]
+mod in_memory;
+
 #[cfg(feature = "redis-actor-session")]
 mod redis_actor;
 
 #[cfg(feature = "redis-rs-session")]
 mod redis_rs;
 
-#[cfg(any(feature = "redis-actor-session", feature = "redis-rs-session"))]
-mod utils;
+#[cfg(feature = "redis-rs-session")]
+mod redis_rs_connection;
+
+#[cfg(feature = "sql-session")]
+mod sql;
+
+#[cfg(any(
+    feature = "in-memory-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session",
+    feature = "csrf"
+))]
+pub(crate) mod utils;
 
 #[cfg(feature = "cookie-session")]
-pub use cookie::CookieSessionStore;
+pub use cookie::{CookieSessionStore, CookieSessionStoreBuilder};
+#[cfg(feature = "in-memory-session")]
+pub use in_memory::{InMemorySessionStore, InMemorySessionStoreBuilder};
 #[cfg(feature = "redis-actor-session")]
 pub use redis_actor::{RedisActorSessionStore, RedisActorSessionStoreBuilder};
 #[cfg(feature = "redis-rs-session")]
 pub use redis_rs::{RedisSessionStore, RedisSessionStoreBuilder};
+#[cfg(feature = "redis-rs-session")]
+pub use redis_rs_connection::RedisConnectionPool;
+#[cfg(feature = "sql-session")]
+pub use sql::{SqlSessionStore, SqlSessionStoreBuilder};
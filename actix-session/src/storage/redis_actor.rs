@@ -5,9 +5,10 @@ use anyhow::Error;
 
 use super::SessionKey;
 use crate::storage::{
+    codec::{decode_state, encode_state, JsonCodec, SessionStateCodec},
     interface::{LoadError, SaveError, SessionState, UpdateError},
-    utils::generate_session_key,
-    SessionStore,
+    utils::{generate_session_key, missing_subject_key, session_subject, INDEX_SESSION_SCRIPT},
+    IndexedSessionStore, SessionStore,
 };
 
 /// Use Redis as session storage backend.
@@ -47,6 +48,13 @@ use crate::storage::{
 /// `RedisActorSessionStore` leverages `actix-redis`'s `RedisActor` implementation - each thread
 /// worker gets its own connection to Redis.
 ///
+/// ## Sessions by subject
+///
+/// `RedisActorSessionStore` implements [`IndexedSessionStore`]: configure the session state key
+/// holding the subject of a session via [`RedisActorSessionStoreBuilder::subject_key`]. The keys of
+/// the sessions belonging to a subject are stored in a Redis set—stale entries (e.g. expired
+/// sessions) are removed when the set is read.
+///
 /// ## Limitations
 ///
 /// `RedisActorSessionStore` does not currently support establishing authenticated connections to
@@ -81,12 +89,16 @@ impl RedisActorSessionStore {
 
 struct CacheConfiguration {
     cache_keygen: Box<dyn Fn(&str) -> String>,
+    codec: Box<dyn SessionStateCodec>,
+    subject_key: Option<String>,
 }
 
 impl Default for CacheConfiguration {
     fn default() -> Self {
         Self {
             cache_keygen: Box::new(str::to_owned),
+            codec: Box::new(JsonCodec),
+            subject_key: None,
         }
     }
 }
@@ -110,6 +122,22 @@ impl RedisActorSessionStoreBuilder {
         self
     }
 
+    /// Set the codec used to encode the session state stored in Redis.
+    ///
+    /// Defaults to [`JsonCodec`](crate::storage::JsonCodec).
+    pub fn codec<C: SessionStateCodec + 'static>(mut self, codec: C) -> Self {
+        self.configuration.codec = Box::new(codec);
+        self
+    }
+
+    /// Set the session state key holding the subject (e.g. the user ID) a session belongs to.
+    ///
+    /// It is required to look up sessions by subject—see [`IndexedSessionStore`].
+    pub fn subject_key<S: Into<String>>(mut self, subject_key: S) -> Self {
+        self.configuration.subject_key = Some(subject_key.into());
+        self
+    }
+
     /// Finalise the builder and return a [`RedisActorSessionStore`] instance.
     #[must_use]
     pub fn build(self) -> RedisActorSessionStore {
@@ -136,12 +164,15 @@ impl SessionStore for RedisActorSessionStore {
         match val {
             RespValue::Error(err) => Err(LoadError::Other(anyhow::anyhow!(err))),
 
-            RespValue::SimpleString(s) => Ok(serde_json::from_str(&s)
-                .map_err(Into::into)
-                .map_err(LoadError::Deserialization)?),
+            RespValue::SimpleString(s) => Ok(decode_state(
+                self.configuration.codec.as_ref(),
+                s.as_bytes(),
+            )
+            .map(Some)
+            .map_err(LoadError::Deserialization)?),
 
-            RespValue::BulkString(s) => Ok(serde_json::from_slice(&s)
-                .map_err(Into::into)
+            RespValue::BulkString(s) => Ok(decode_state(self.configuration.codec.as_ref(), &s)
+                .map(Some)
                 .map_err(LoadError::Deserialization)?),
 
             _ => Ok(None),
@@ -153,8 +184,7 @@ impl SessionStore for RedisActorSessionStore {
         session_state: SessionState,
         ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
-        let body = serde_json::to_string(&session_state)
-            .map_err(Into::into)
+        let body = encode_state(self.configuration.codec.as_ref(), &session_state)
             .map_err(SaveError::Serialization)?;
         let session_key = generate_session_key();
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
@@ -178,7 +208,13 @@ impl SessionStore for RedisActorSessionStore {
             .map_err(SaveError::Other)?;
 
         match result {
-            RespValue::SimpleString(_) => Ok(session_key),
+            RespValue::SimpleString(_) => {
+                self.index_session(&session_key, &session_state, ttl)
+                    .await
+                    .map_err(SaveError::Other)?;
+
+                Ok(session_key)
+            }
             RespValue::Nil => Err(SaveError::Other(anyhow::anyhow!(
                 "Failed to save session state. A record with the same key already existed in Redis"
             ))),
@@ -195,8 +231,7 @@ impl SessionStore for RedisActorSessionStore {
         session_state: SessionState,
         ttl: &Duration,
     ) -> Result<SessionKey, UpdateError> {
-        let body = serde_json::to_string(&session_state)
-            .map_err(Into::into)
+        let body = encode_state(self.configuration.codec.as_ref(), &session_state)
             .map_err(UpdateError::Serialization)?;
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
 
@@ -231,7 +266,13 @@ impl SessionStore for RedisActorSessionStore {
                         SaveError::Other(err) => UpdateError::Other(err),
                     })
             }
-            RespValue::SimpleString(_) => Ok(session_key),
+            RespValue::SimpleString(_) => {
+                self.index_session(&session_key, &session_state, ttl)
+                    .await
+                    .map_err(UpdateError::Other)?;
+
+                Ok(session_key)
+            }
             val => Err(UpdateError::Other(anyhow::anyhow!(
                 "Failed to update session state. {:?}",
                 val
@@ -249,12 +290,23 @@ impl SessionStore for RedisActorSessionStore {
         ]);
 
         match self.addr.send(cmd).await? {
-            Ok(RespValue::Integer(_)) => Ok(()),
-            val => Err(anyhow::anyhow!(
-                "Failed to update the session state TTL: {:?}",
-                val
-            )),
+            Ok(RespValue::Integer(_)) => {}
+            val => {
+                return Err(anyhow::anyhow!(
+                    "Failed to update the session state TTL: {:?}",
+                    val
+                ))
+            }
+        }
+
+        // the subject index must outlive the session
+        if self.configuration.subject_key.is_some() {
+            if let Ok(Some(session_state)) = self.load(session_key).await {
+                self.index_session(session_key, &session_state, ttl).await?;
+            }
         }
+
+        Ok(())
     }
 
     async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
@@ -276,6 +328,143 @@ impl SessionStore for RedisActorSessionStore {
     }
 }
 
+#[async_trait::async_trait(?Send)]
+impl IndexedSessionStore for RedisActorSessionStore {
+    async fn sessions_for(&self, subject: &str) -> Result<Vec<SessionKey>, Error> {
+        let subject_key = self
+            .configuration
+            .subject_key
+            .as_deref()
+            .ok_or_else(missing_subject_key)?;
+        let index_key = self.index_key(subject);
+
+        let members = match self
+            .addr
+            .send(Command(resp_array!["SMEMBERS", &index_key]))
+            .await?
+        {
+            Ok(RespValue::Array(members)) => members,
+            val => {
+                return Err(anyhow::anyhow!(
+                    "Failed to list the sessions belonging to a subject. {:?}",
+                    val
+                ))
+            }
+        };
+
+        let mut session_keys = Vec::with_capacity(members.len());
+        for member in members {
+            let session_key = match member {
+                RespValue::BulkString(member) => {
+                    SessionKey::new_unbounded(String::from_utf8(member)?)
+                }
+                val => {
+                    return Err(anyhow::anyhow!(
+                        "Failed to list the sessions belonging to a subject. {:?}",
+                        val
+                    ))
+                }
+            };
+
+            // The index is not updated when a session state expires, is deleted or changes
+            // subject: we verify that each session still belongs to the subject.
+            let belongs_to_subject = match self.load(&session_key).await {
+                Ok(Some(session_state)) => {
+                    session_subject(&session_state, subject_key).as_deref() == Some(subject)
+                }
+                Ok(None) | Err(LoadError::Deserialization(_)) => false,
+                Err(LoadError::Other(err)) => return Err(err),
+            };
+
+            if belongs_to_subject {
+                session_keys.push(session_key);
+            } else {
+                self.unindex_session(&index_key, &session_key).await?;
+            }
+        }
+
+        Ok(session_keys)
+    }
+
+    async fn delete_all_for(&self, subject: &str) -> Result<usize, Error> {
+        let session_keys = self.sessions_for(subject).await?;
+        let index_key = self.index_key(subject);
+
+        for session_key in &session_keys {
+            self.delete(session_key).await?;
+            self.unindex_session(&index_key, session_key).await?;
+        }
+
+        Ok(session_keys.len())
+    }
+}
+
+impl RedisActorSessionStore {
+    /// The key of the Redis set holding the keys of the sessions belonging to `subject`.
+    ///
+    /// It can never clash with the cache key of a session: session keys are alphanumeric.
+    fn index_key(&self, subject: &str) -> String {
+        (self.configuration.cache_keygen)(&format!("subject:{}", subject))
+    }
+
+    /// Adds the session to the index of the sessions belonging to its subject, if it has one.
+    ///
+    /// The index is set to expire no sooner than the session, i.e. in `ttl`.
+    async fn index_session(
+        &self,
+        session_key: &SessionKey,
+        session_state: &SessionState,
+        ttl: &Duration,
+    ) -> Result<(), Error> {
+        let subject = self
+            .configuration
+            .subject_key
+            .as_deref()
+            .and_then(|subject_key| session_subject(session_state, subject_key));
+
+        if let Some(subject) = subject {
+            let cmd = Command(resp_array![
+                "EVAL",
+                INDEX_SESSION_SCRIPT,
+                "1",
+                self.index_key(&subject),
+                session_key.as_ref(),
+                ttl.whole_seconds().to_string()
+            ]);
+
+            match self.addr.send(cmd).await? {
+                Ok(RespValue::Integer(_)) => {}
+                val => {
+                    return Err(anyhow::anyhow!(
+                        "Failed to index the session by subject. {:?}",
+                        val
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the session from the index of the sessions belonging to a subject.
+    async fn unindex_session(
+        &self,
+        index_key: &str,
+        session_key: &SessionKey,
+    ) -> Result<(), Error> {
+        let cmd = Command(resp_array!["SREM", index_key, session_key.as_ref()]);
+
+        match self.addr.send(cmd).await? {
+            // Redis returns the number of removed members
+            Ok(RespValue::Integer(_)) => Ok(()),
+            val => Err(anyhow::anyhow!(
+                "Failed to remove the session from the subject index. {:?}",
+                val
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -283,7 +472,7 @@ mod tests {
     use actix_web::cookie::time::Duration;
 
     use super::*;
-    use crate::test_helpers::acceptance_test_suite;
+    use crate::test_helpers::{acceptance_test_suite, subject_index_test_suite};
 
     fn redis_actor_store() -> RedisActorSessionStore {
         RedisActorSessionStore::new("127.0.0.1:6379")
@@ -312,4 +501,54 @@ mod tests {
             .unwrap();
         assert_ne!(initial_session_key, updated_session_key.as_ref());
     }
+
+    #[actix_web::test]
+    async fn sessions_can_be_looked_up_and_deleted_by_subject() {
+        let store = RedisActorSessionStore::builder("127.0.0.1:6379")
+            .subject_key("user_id")
+            .build();
+        subject_index_test_suite(store).await;
+    }
+
+    #[actix_web::test]
+    async fn subject_indexes_expire_no_sooner_than_their_sessions() {
+        let store = RedisActorSessionStore::builder("127.0.0.1:6379")
+            .subject_key("user_id")
+            .build();
+        let subject = generate_session_key();
+        let mut session_state = HashMap::new();
+        session_state.insert("user_id".to_owned(), subject.as_ref().into());
+        let index_ttl = || async {
+            let cmd = Command(resp_array!["TTL", store.index_key(subject.as_ref())]);
+            match store.addr.send(cmd).await.unwrap().unwrap() {
+                RespValue::Integer(ttl) => ttl,
+                val => panic!("Unexpected TTL: {:?}", val),
+            }
+        };
+
+        let session_key = store
+            .save(session_state.clone(), &Duration::minutes(1))
+            .await
+            .unwrap();
+        assert!((1..=60).contains(&index_ttl().await));
+
+        store
+            .save(session_state.clone(), &Duration::minutes(10))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 60);
+
+        // the TTL of the index is never shortened
+        store
+            .save(session_state, &Duration::seconds(10))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 60);
+
+        store
+            .update_ttl(&session_key, &Duration::hours(1))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 600);
+    }
 }
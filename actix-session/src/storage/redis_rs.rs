@@ -2,13 +2,20 @@ use std::{convert::TryInto, sync::Arc};
 
 use actix_web::cookie::time::Duration;
 use anyhow::{Context, Error};
-use redis::{aio::ConnectionManager, AsyncCommands, Cmd, FromRedisValue, RedisResult, Value};
+use redis::{aio::ConnectionManager, Cmd, FromRedisValue, RedisResult, Value};
+use sha1::{Digest as _, Sha1};
 
 use super::SessionKey;
 use crate::storage::{
-    interface::{LoadError, SaveError, SessionState, UpdateError},
-    utils::generate_session_key,
-    SessionStore,
+    codec::{decode_state, encode_state, JsonCodec, SessionStateCodec},
+    interface::{
+        LoadError, SaveError, SessionState, SessionVersion, UpdateError, VersionedSessionState,
+    },
+    redis_rs_connection::{
+        ClusterConnection, RedisConnection, RedisConnectionPool, SentinelConnection,
+    },
+    utils::{generate_session_key, missing_subject_key, session_subject, INDEX_SESSION_SCRIPT},
+    IndexedSessionStore, SessionStore,
 };
 
 /// Use Redis as session storage backend.
@@ -56,6 +63,83 @@ use crate::storage::{
 /// # })
 /// ```
 ///
+/// # Sharing connections
+/// [`RedisSessionStore::new`] and [`RedisSessionStore::builder`] open a dedicated connection to
+/// Redis. You can share the connection (and its TLS/authentication settings) with the rest of your
+/// application instead:
+///
+/// - pass a [`ConnectionManager`] to [`RedisSessionStore::from_connection_manager`];
+/// - or pass a connection pool to [`RedisSessionStore::from_pool`], by implementing
+///   [`RedisConnectionPool`] for the pool type of your pooling library (e.g. `deadpool-redis` or
+///   `bb8-redis`).
+///
+/// ```no_run
+/// use actix_session::storage::RedisSessionStore;
+/// use redis::aio::ConnectionManager;
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let client = redis::Client::open("redis://127.0.0.1:6379").unwrap();
+/// let connection = ConnectionManager::new(client).await.unwrap();
+///
+/// let store = RedisSessionStore::from_connection_manager(connection.clone());
+/// # })
+/// ```
+///
+/// Commands that fail because the connection was dropped are retried once—with a different
+/// connection, when using a pool.
+///
+/// # Redis Cluster
+/// Use [`RedisSessionStore::builder_cluster`] to store session states in a Redis Cluster. Each
+/// command is routed to the node serving the hash slot of its key; the slot map is refreshed when
+/// Redis reports that a slot moved (`MOVED`/`ASK` redirections) or when a node becomes unreachable.
+///
+/// ```no_run
+/// use actix_session::storage::RedisSessionStore;
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let store = RedisSessionStore::builder_cluster([
+///     "redis://127.0.0.1:7000",
+///     "redis://127.0.0.1:7001",
+///     "redis://127.0.0.1:7002",
+/// ])
+/// .build()
+/// .await
+/// .unwrap();
+/// # })
+/// ```
+///
+/// The credentials and TLS settings of the first connection string are used to connect to all
+/// the nodes of the cluster.
+///
+/// # Redis Sentinel
+/// Use [`RedisSessionStore::builder_sentinel`] to connect to the master of a group of Redis
+/// instances monitored by Redis Sentinel. The master is discovered again if the connection to it
+/// fails or if it has been demoted to replica, following a failover.
+///
+/// ```no_run
+/// use actix_session::storage::RedisSessionStore;
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let store = RedisSessionStore::builder_sentinel(
+///     ["redis://127.0.0.1:26379", "redis://127.0.0.1:26380"],
+///     "mymaster",
+/// )
+/// .build()
+/// .await
+/// .unwrap();
+/// # })
+/// ```
+///
+/// The master is connected to using the credentials and TLS settings of the sentinel that
+/// reported its address.
+///
+/// # Sessions by subject
+/// `RedisSessionStore` implements [`IndexedSessionStore`]: configure the session state key holding
+/// the subject of a session via [`RedisSessionStoreBuilder::subject_key`]. The keys of the sessions
+/// belonging to a subject are stored in a Redis set—stale entries (e.g. expired sessions) are
+/// removed when the set is read. Each session is stored alongside the key of the set it belongs
+/// to, so that extending its TTL extends the TTL of the set without loading the session state.
+///
 /// # Implementation notes
 /// `RedisSessionStore` leverages [`redis-rs`] as Redis client.
 ///
@@ -64,18 +148,22 @@ use crate::storage::{
 #[derive(Clone)]
 pub struct RedisSessionStore {
     configuration: CacheConfiguration,
-    client: ConnectionManager,
+    client: RedisConnection,
 }
 
 #[derive(Clone)]
 struct CacheConfiguration {
     cache_keygen: Arc<dyn Fn(&str) -> String + Send + Sync>,
+    codec: Arc<dyn SessionStateCodec>,
+    subject_key: Option<Arc<str>>,
 }
 
 impl Default for CacheConfiguration {
     fn default() -> Self {
         Self {
             cache_keygen: Arc::new(str::to_owned),
+            codec: Arc::new(JsonCodec),
+            subject_key: None,
         }
     }
 }
@@ -87,7 +175,86 @@ impl RedisSessionStore {
     pub fn builder<S: Into<String>>(connection_string: S) -> RedisSessionStoreBuilder {
         RedisSessionStoreBuilder {
             configuration: CacheConfiguration::default(),
-            connection_string: connection_string.into(),
+            deployment: RedisDeployment::Single(connection_string.into()),
+        }
+    }
+
+    /// A fluent API to configure a [`RedisSessionStore`] sharing an existing connection.
+    ///
+    /// See [Sharing connections](#sharing-connections) for more details.
+    pub fn builder_from_connection_manager(
+        connection: ConnectionManager,
+    ) -> RedisSessionStoreBuilder {
+        RedisSessionStoreBuilder {
+            configuration: CacheConfiguration::default(),
+            deployment: RedisDeployment::Connected(RedisConnection::Single(connection)),
+        }
+    }
+
+    /// Create a new instance of [`RedisSessionStore`] sharing an existing connection, using the
+    /// default configuration.
+    ///
+    /// See [Sharing connections](#sharing-connections) for more details.
+    pub fn from_connection_manager(connection: ConnectionManager) -> RedisSessionStore {
+        RedisSessionStore {
+            configuration: CacheConfiguration::default(),
+            client: RedisConnection::Single(connection),
+        }
+    }
+
+    /// A fluent API to configure a [`RedisSessionStore`] borrowing connections from a pool.
+    ///
+    /// See [Sharing connections](#sharing-connections) for more details.
+    pub fn builder_pooled<P: RedisConnectionPool>(pool: P) -> RedisSessionStoreBuilder {
+        RedisSessionStoreBuilder {
+            configuration: CacheConfiguration::default(),
+            deployment: RedisDeployment::Connected(RedisConnection::Pooled(Arc::new(pool))),
+        }
+    }
+
+    /// Create a new instance of [`RedisSessionStore`] borrowing connections from a pool, using
+    /// the default configuration.
+    ///
+    /// See [Sharing connections](#sharing-connections) for more details.
+    pub fn from_pool<P: RedisConnectionPool>(pool: P) -> RedisSessionStore {
+        RedisSessionStore {
+            configuration: CacheConfiguration::default(),
+            client: RedisConnection::Pooled(Arc::new(pool)),
+        }
+    }
+
+    /// A fluent API to configure a [`RedisSessionStore`] backed by a Redis Cluster.
+    ///
+    /// It takes as input the connection strings of one or more nodes of the cluster, used to
+    /// discover the others. See [Redis Cluster](#redis-cluster) for more details.
+    pub fn builder_cluster<I, S>(nodes: I) -> RedisSessionStoreBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        RedisSessionStoreBuilder {
+            configuration: CacheConfiguration::default(),
+            deployment: RedisDeployment::Cluster(nodes.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// A fluent API to configure a [`RedisSessionStore`] connected to a master discovered via
+    /// Redis Sentinel.
+    ///
+    /// It takes as input the connection strings of one or more sentinels and the name of the
+    /// master they monitor. See [Redis Sentinel](#redis-sentinel) for more details.
+    pub fn builder_sentinel<I, S, M>(sentinels: I, master_name: M) -> RedisSessionStoreBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+        M: Into<String>,
+    {
+        RedisSessionStoreBuilder {
+            configuration: CacheConfiguration::default(),
+            deployment: RedisDeployment::Sentinel {
+                sentinels: sentinels.into_iter().map(Into::into).collect(),
+                master_name: master_name.into(),
+            },
         }
     }
 
@@ -108,10 +275,20 @@ impl RedisSessionStore {
 #[cfg_attr(docsrs, doc(cfg(feature = "redis-rs-session")))]
 #[must_use]
 pub struct RedisSessionStoreBuilder {
-    connection_string: String,
+    deployment: RedisDeployment,
     configuration: CacheConfiguration,
 }
 
+enum RedisDeployment {
+    Single(String),
+    Connected(RedisConnection),
+    Cluster(Vec<String>),
+    Sentinel {
+        sentinels: Vec<String>,
+        master_name: String,
+    },
+}
+
 impl RedisSessionStoreBuilder {
     /// Set a custom cache key generation strategy, expecting a session key as input.
     pub fn cache_keygen<F>(mut self, keygen: F) -> Self
@@ -122,11 +299,40 @@ impl RedisSessionStoreBuilder {
         self
     }
 
-    /// Finalise the builder and return a [`RedisActorSessionStore`] instance.
+    /// Set the codec used to encode the session state stored in Redis.
+    ///
+    /// Defaults to [`JsonCodec`](crate::storage::JsonCodec).
+    pub fn codec<C: SessionStateCodec + 'static>(mut self, codec: C) -> Self {
+        self.configuration.codec = Arc::new(codec);
+        self
+    }
+
+    /// Set the session state key holding the subject (e.g. the user ID) a session belongs to.
     ///
-    /// [`RedisActorSessionStore`]: crate::storage::RedisActorSessionStore
+    /// It is required to look up sessions by subject—see [`IndexedSessionStore`].
+    pub fn subject_key<S: Into<String>>(mut self, subject_key: S) -> Self {
+        self.configuration.subject_key = Some(subject_key.into().into());
+        self
+    }
+
+    /// Finalise the builder and return a [`RedisSessionStore`] instance.
     pub async fn build(self) -> Result<RedisSessionStore, anyhow::Error> {
-        let client = ConnectionManager::new(redis::Client::open(self.connection_string)?).await?;
+        let client = match self.deployment {
+            RedisDeployment::Single(connection_string) => RedisConnection::Single(
+                ConnectionManager::new(redis::Client::open(connection_string)?).await?,
+            ),
+            RedisDeployment::Connected(client) => client,
+            RedisDeployment::Cluster(nodes) => {
+                RedisConnection::Cluster(ClusterConnection::connect(&nodes).await?)
+            }
+            RedisDeployment::Sentinel {
+                sentinels,
+                master_name,
+            } => RedisConnection::Sentinel(
+                SentinelConnection::connect(&sentinels, &master_name).await?,
+            ),
+        };
+
         Ok(RedisSessionStore {
             configuration: self.configuration,
             client,
@@ -137,20 +343,10 @@ impl RedisSessionStoreBuilder {
 #[async_trait::async_trait(?Send)]
 impl SessionStore for RedisSessionStore {
     async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
-        let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
-
-        let value: Option<String> = self
-            .execute_command(redis::cmd("GET").arg(&[&cache_key]))
-            .await
-            .map_err(Into::into)
-            .map_err(LoadError::Other)?;
-
-        match value {
-            None => Ok(None),
-            Some(value) => Ok(serde_json::from_str(&value)
-                .map_err(Into::into)
-                .map_err(LoadError::Deserialization)?),
-        }
+        Ok(self
+            .load_versioned(session_key)
+            .await?
+            .map(|versioned| versioned.state))
     }
 
     async fn save(
@@ -158,15 +354,12 @@ impl SessionStore for RedisSessionStore {
         session_state: SessionState,
         ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
-        let body = serde_json::to_string(&session_state)
-            .map_err(Into::into)
+        let body = encode_state(self.configuration.codec.as_ref(), &session_state)
             .map_err(SaveError::Serialization)?;
         let session_key = generate_session_key();
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
 
-        self.execute_command(redis::cmd("SET").arg(&[
-            &cache_key,
-            &body,
+        self.execute_command(redis::cmd("SET").arg(&cache_key).arg(body).arg(&[
             "NX", // NX: only set the key if it does not already exist
             "EX", // EX: set expiry
             &format!("{}", ttl.whole_seconds()),
@@ -175,6 +368,11 @@ impl SessionStore for RedisSessionStore {
         .map_err(Into::into)
         .map_err(SaveError::Other)?;
 
+        self.index_session(&session_key, &session_state, ttl)
+            .await
+            .map_err(Into::into)
+            .map_err(SaveError::Other)?;
+
         Ok(session_key)
     }
 
@@ -184,16 +382,13 @@ impl SessionStore for RedisSessionStore {
         session_state: SessionState,
         ttl: &Duration,
     ) -> Result<SessionKey, UpdateError> {
-        let body = serde_json::to_string(&session_state)
-            .map_err(Into::into)
+        let body = encode_state(self.configuration.codec.as_ref(), &session_state)
             .map_err(UpdateError::Serialization)?;
 
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
 
         let v: redis::Value = self
-            .execute_command(redis::cmd("SET").arg(&[
-                &cache_key,
-                &body,
+            .execute_command(redis::cmd("SET").arg(&cache_key).arg(body).arg(&[
                 "XX", // XX: Only set the key if it already exist.
                 "EX", // EX: set expiry
                 &format!("{}", ttl.whole_seconds()),
@@ -215,7 +410,14 @@ impl SessionStore for RedisSessionStore {
                         SaveError::Other(err) => UpdateError::Other(err),
                     })
             }
-            Value::Int(_) | Value::Okay | Value::Status(_) => Ok(session_key),
+            Value::Int(_) | Value::Okay | Value::Status(_) => {
+                self.index_session(&session_key, &session_state, ttl)
+                    .await
+                    .map_err(Into::into)
+                    .map_err(UpdateError::Other)?;
+
+                Ok(session_key)
+            }
             val => Err(UpdateError::Other(anyhow::anyhow!(
                 "Failed to update session state. {:?}",
                 val
@@ -225,16 +427,39 @@ impl SessionStore for RedisSessionStore {
 
     async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), Error> {
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
+        let ttl_seconds: usize = ttl.whole_seconds().try_into().context(
+            "Failed to convert the state TTL into the number of whole seconds remaining",
+        )?;
 
-        self.client
-            .clone()
-            .expire(
-                &cache_key,
-                ttl.whole_seconds().try_into().context(
-                    "Failed to convert the state TTL into the number of whole seconds remaining",
-                )?,
-            )
+        let exists: bool = self
+            .execute_command(redis::cmd("EXPIRE").arg(&cache_key).arg(ttl_seconds))
             .await?;
+
+        // the subject index must outlive the session
+        if exists && self.configuration.subject_key.is_some() {
+            let index_key: Option<String> = self
+                .execute_command(
+                    redis::cmd("EVAL")
+                        .arg(TOUCH_INDEX_POINTER_SCRIPT)
+                        .arg(1)
+                        .arg(self.index_pointer_key(session_key))
+                        .arg(ttl_seconds),
+                )
+                .await?;
+
+            if let Some(index_key) = index_key {
+                self.execute_command::<()>(
+                    redis::cmd("EVAL")
+                        .arg(INDEX_SESSION_SCRIPT)
+                        .arg(1)
+                        .arg(index_key)
+                        .arg(session_key.as_ref())
+                        .arg(ttl_seconds),
+                )
+                .await?;
+            }
+        }
+
         Ok(())
     }
 
@@ -247,9 +472,259 @@ impl SessionStore for RedisSessionStore {
 
         Ok(())
     }
+
+    /// The version of a session state is the SHA-1 hash of its stored representation.
+    async fn load_versioned(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<VersionedSessionState>, LoadError> {
+        let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
+
+        let value: Option<Vec<u8>> = self
+            .execute_command(redis::cmd("GET").arg(&[&cache_key]))
+            .await
+            .map_err(Into::into)
+            .map_err(LoadError::Other)?;
+
+        match value {
+            None => Ok(None),
+            Some(value) => {
+                let state = decode_state(self.configuration.codec.as_ref(), &value)
+                    .map_err(LoadError::Deserialization)?;
+
+                Ok(Some(VersionedSessionState {
+                    state,
+                    version: Some(SessionVersion::new(sha1_hex(&value))),
+                }))
+            }
+        }
+    }
+
+    async fn update_versioned(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        version: &SessionVersion,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let body = encode_state(self.configuration.codec.as_ref(), &session_state)
+            .map_err(UpdateError::Serialization)?;
+
+        let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
+
+        let outcome: i64 = self
+            .execute_command(
+                redis::cmd("EVAL")
+                    .arg(COMPARE_AND_SET_SCRIPT)
+                    .arg(1)
+                    .arg(&cache_key)
+                    .arg(version.as_ref())
+                    .arg(body)
+                    .arg(ttl.whole_seconds()),
+            )
+            .await
+            .map_err(Into::into)
+            .map_err(UpdateError::Other)?;
+
+        match outcome {
+            // The session state expired since it was loaded: there is nothing left to conflict
+            // with.
+            0 => self.update(session_key, session_state, ttl).await,
+            1 => {
+                self.index_session(&session_key, &session_state, ttl)
+                    .await
+                    .map_err(Into::into)
+                    .map_err(UpdateError::Other)?;
+
+                Ok(session_key)
+            }
+            _ => Err(UpdateError::Conflict),
+        }
+    }
+}
+
+/// Overwrites the session state stored at `KEYS[1]` if the SHA-1 hash of its current value is
+/// `ARGV[1]`.
+///
+/// Returns `0` if there is no session state, `-1` if it has been modified and `1` on success.
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if not current then
+    return 0
+end
+if redis.sha1hex(current) ~= ARGV[1] then
+    return -1
+end
+redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+return 1
+"#;
+
+/// Extends the TTL of `KEYS[1]`, the pointer from a session to the index of its subject, to
+/// `ARGV[1]` seconds.
+///
+/// Returns the key of the index, or nil if the session does not belong to a subject.
+const TOUCH_INDEX_POINTER_SCRIPT: &str = r#"
+local index_key = redis.call('GET', KEYS[1])
+if index_key then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+return index_key
+"#;
+
+fn sha1_hex(value: &[u8]) -> String {
+    Sha1::digest(value)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[async_trait::async_trait(?Send)]
+impl IndexedSessionStore for RedisSessionStore {
+    async fn sessions_for(&self, subject: &str) -> Result<Vec<SessionKey>, Error> {
+        let subject_key = self
+            .configuration
+            .subject_key
+            .as_deref()
+            .ok_or_else(missing_subject_key)?;
+        let index_key = self.index_key(subject);
+
+        let members: Vec<String> = self
+            .execute_command(redis::cmd("SMEMBERS").arg(&index_key))
+            .await?;
+        if members.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let cache_keys: Vec<String> = members
+            .iter()
+            .map(|member| (self.configuration.cache_keygen)(member))
+            .collect();
+        let values: Vec<Option<Vec<u8>>> = if self.client.is_cluster() {
+            // the session states are spread across hash slots, a single MGET cannot reach them all
+            let mut values = Vec::with_capacity(cache_keys.len());
+            for cache_key in &cache_keys {
+                values.push(
+                    self.execute_command(redis::cmd("GET").arg(cache_key))
+                        .await?,
+                );
+            }
+            values
+        } else {
+            self.execute_command(redis::cmd("MGET").arg(&cache_keys))
+                .await?
+        };
+
+        let mut session_keys = Vec::with_capacity(members.len());
+        let mut stale_members = Vec::new();
+        for (member, value) in members.into_iter().zip(values) {
+            // The index is not updated when a session state expires, is deleted or changes
+            // subject: we verify that each session still belongs to the subject.
+            let belongs_to_subject = value
+                .and_then(|value| decode_state(self.configuration.codec.as_ref(), &value).ok())
+                .is_some_and(|session_state| {
+                    session_subject(&session_state, subject_key).as_deref() == Some(subject)
+                });
+
+            if belongs_to_subject {
+                session_keys.push(SessionKey::new_unbounded(member));
+            } else {
+                stale_members.push(member);
+            }
+        }
+
+        if !stale_members.is_empty() {
+            self.execute_command::<()>(redis::cmd("SREM").arg(&index_key).arg(&stale_members))
+                .await?;
+        }
+
+        Ok(session_keys)
+    }
+
+    async fn delete_all_for(&self, subject: &str) -> Result<usize, Error> {
+        let session_keys = self.sessions_for(subject).await?;
+        if session_keys.is_empty() {
+            return Ok(0);
+        }
+
+        for session_key in &session_keys {
+            self.delete(session_key).await?;
+        }
+
+        let members: Vec<&str> = session_keys.iter().map(AsRef::as_ref).collect();
+        self.execute_command::<()>(
+            redis::cmd("SREM")
+                .arg(self.index_key(subject))
+                .arg(&members),
+        )
+        .await?;
+
+        Ok(session_keys.len())
+    }
 }
 
 impl RedisSessionStore {
+    /// The key of the Redis set holding the keys of the sessions belonging to `subject`.
+    ///
+    /// It can never clash with the cache key of a session: session keys are alphanumeric.
+    fn index_key(&self, subject: &str) -> String {
+        (self.configuration.cache_keygen)(&format!("subject:{}", subject))
+    }
+
+    /// The key pointing from a session to the index of the sessions belonging to its subject.
+    ///
+    /// It can never clash with the cache key of a session: session keys are alphanumeric.
+    fn index_pointer_key(&self, session_key: &SessionKey) -> String {
+        (self.configuration.cache_keygen)(&format!("subject-index:{}", session_key.as_ref()))
+    }
+
+    /// Adds the session to the index of the sessions belonging to its subject, if it has one.
+    ///
+    /// The index is set to expire no sooner than the session, i.e. in `ttl`. The key of the index
+    /// is stored next to the session, to extend its TTL alongside the TTL of the session without
+    /// loading the session state.
+    async fn index_session(
+        &self,
+        session_key: &SessionKey,
+        session_state: &SessionState,
+        ttl: &Duration,
+    ) -> RedisResult<()> {
+        let subject_key = match self.configuration.subject_key.as_deref() {
+            Some(subject_key) => subject_key,
+            None => return Ok(()),
+        };
+        let pointer_key = self.index_pointer_key(session_key);
+
+        match session_subject(session_state, subject_key) {
+            Some(subject) => {
+                let index_key = self.index_key(&subject);
+
+                self.execute_command::<()>(
+                    redis::cmd("SET")
+                        .arg(&pointer_key)
+                        .arg(&index_key)
+                        .arg("EX")
+                        .arg(ttl.whole_seconds()),
+                )
+                .await?;
+                self.execute_command::<()>(
+                    redis::cmd("EVAL")
+                        .arg(INDEX_SESSION_SCRIPT)
+                        .arg(1)
+                        .arg(index_key)
+                        .arg(session_key.as_ref())
+                        .arg(ttl.whole_seconds()),
+                )
+                .await?;
+            }
+            None => {
+                self.execute_command::<()>(redis::cmd("DEL").arg(&pointer_key))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Execute Redis command and retry once in certain cases.
     ///
     /// `ConnectionManager` automatically reconnects when it encounters an error talking to Redis.
@@ -264,27 +739,7 @@ impl RedisSessionStore {
     /// retry will be executed on a fresh connection, therefore it is likely to succeed (or fail for
     /// a different more meaningful reason).
     async fn execute_command<T: FromRedisValue>(&self, cmd: &mut Cmd) -> RedisResult<T> {
-        let mut can_retry = true;
-
-        loop {
-            match cmd.query_async(&mut self.client.clone()).await {
-                Ok(value) => return Ok(value),
-                Err(err) => {
-                    if can_retry && err.is_connection_dropped() {
-                        tracing::debug!(
-                            "Connection dropped while trying to talk to Redis. Retrying."
-                        );
-
-                        // Retry at most once
-                        can_retry = false;
-
-                        continue;
-                    } else {
-                        return Err(err);
-                    }
-                }
-            }
-        }
+        self.client.query(cmd).await
     }
 }
 
@@ -293,10 +748,11 @@ mod tests {
     use std::collections::HashMap;
 
     use actix_web::cookie::time;
-    use redis::AsyncCommands;
 
     use super::*;
-    use crate::test_helpers::acceptance_test_suite;
+    use crate::test_helpers::{
+        acceptance_test_suite, optimistic_concurrency_test_suite, subject_index_test_suite,
+    };
 
     async fn redis_store() -> RedisSessionStore {
         RedisSessionStore::new("redis://127.0.0.1:6379")
@@ -310,6 +766,14 @@ mod tests {
         acceptance_test_suite(move || redis_store.clone(), true).await;
     }
 
+    #[actix_web::test]
+    async fn test_session_workflow_with_a_shared_connection() {
+        let client = redis::Client::open("redis://127.0.0.1:6379").unwrap();
+        let connection = ConnectionManager::new(client).await.unwrap();
+        let redis_store = RedisSessionStore::from_connection_manager(connection);
+        acceptance_test_suite(move || redis_store.clone(), true).await;
+    }
+
     #[actix_web::test]
     async fn loading_a_missing_session_returns_none() {
         let store = redis_store().await;
@@ -322,9 +786,11 @@ mod tests {
         let store = redis_store().await;
         let session_key = generate_session_key();
         store
-            .client
-            .clone()
-            .set::<_, _, ()>(session_key.as_ref(), "random-thing-which-is-not-json")
+            .execute_command::<()>(
+                redis::cmd("SET")
+                    .arg(session_key.as_ref())
+                    .arg("random-thing-which-is-not-json"),
+            )
             .await
             .unwrap();
         assert!(matches!(
@@ -344,4 +810,92 @@ mod tests {
             .unwrap();
         assert_ne!(initial_session_key, updated_session_key.as_ref());
     }
+
+    #[actix_web::test]
+    async fn sessions_can_be_looked_up_and_deleted_by_subject() {
+        let store = RedisSessionStore::builder("redis://127.0.0.1:6379")
+            .subject_key("user_id")
+            .build()
+            .await
+            .unwrap();
+        subject_index_test_suite(store).await;
+    }
+
+    #[actix_web::test]
+    async fn subject_indexes_expire_no_sooner_than_their_sessions() {
+        let store = RedisSessionStore::builder("redis://127.0.0.1:6379")
+            .subject_key("user_id")
+            .build()
+            .await
+            .unwrap();
+        let subject = generate_session_key();
+        let mut session_state = HashMap::new();
+        session_state.insert("user_id".to_owned(), subject.as_ref().into());
+        let index_ttl = || async {
+            store
+                .execute_command::<i64>(redis::cmd("TTL").arg(store.index_key(subject.as_ref())))
+                .await
+                .unwrap()
+        };
+
+        let session_key = store
+            .save(session_state.clone(), &time::Duration::minutes(1))
+            .await
+            .unwrap();
+        assert!((1..=60).contains(&index_ttl().await));
+
+        store
+            .save(session_state.clone(), &time::Duration::minutes(10))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 60);
+
+        // the TTL of the index is never shortened
+        store
+            .save(session_state, &time::Duration::seconds(10))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 60);
+
+        store
+            .update_ttl(&session_key, &time::Duration::hours(1))
+            .await
+            .unwrap();
+        assert!(index_ttl().await > 600);
+    }
+
+    #[test]
+    fn session_versions_match_the_sha1_digests_computed_by_lua_scripts() {
+        // `redis.sha1hex("abc")`
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[actix_web::test]
+    async fn concurrent_updates_are_detected() {
+        optimistic_concurrency_test_suite(redis_store().await).await;
+    }
+
+    // Run with `cargo test -- --ignored` against a local Redis Cluster whose nodes listen on ports
+    // 7000 to 7005 (e.g. started using Redis' `create-cluster` utility script).
+    #[actix_web::test]
+    #[ignore = "requires a local Redis Cluster"]
+    async fn test_session_workflow_against_a_cluster() {
+        let store = RedisSessionStore::builder_cluster(["redis://127.0.0.1:7000"])
+            .build()
+            .await
+            .unwrap();
+        acceptance_test_suite(move || store.clone(), true).await;
+    }
+
+    // Run with `cargo test -- --ignored` against a local Redis Sentinel listening on port 26379,
+    // monitoring a master named `mymaster`.
+    #[actix_web::test]
+    #[ignore = "requires a local Redis Sentinel"]
+    async fn test_session_workflow_against_a_sentinel_master() {
+        let store = RedisSessionStore::builder_sentinel(["redis://127.0.0.1:26379"], "mymaster")
+            .build()
+            .await
+            .unwrap();
+        acceptance_test_suite(move || store.clone(), true).await;
+    }
 }
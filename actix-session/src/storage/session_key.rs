@@ -9,6 +9,10 @@ use derive_more::{Display, From};
 /// Session keys are stored as cookies, therefore they cannot be arbitrary long. Session keys are
 /// required to be smaller than 4064 bytes.
 ///
+/// The only exception are the session keys produced by `CookieSessionStore`, which embed the whole
+/// session state: [`SessionMiddleware`] splits them across multiple cookies if allowed to do so via
+/// [`SessionMiddlewareBuilder::cookie_max_chunks`].
+///
 /// ```rust
 /// # use std::convert::TryInto;
 /// use actix_session::storage::SessionKey;
@@ -17,14 +21,48 @@ use derive_more::{Display, From};
 /// let session_key: Result<SessionKey, _> = key.try_into();
 /// assert!(session_key.is_err());
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+///
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+/// [`SessionMiddlewareBuilder::cookie_max_chunks`]: crate::config::SessionMiddlewareBuilder::cookie_max_chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SessionKey(String);
 
+/// The upper limit on the content of a single cookie.
+pub(crate) const MAX_COOKIE_CONTENT_LENGTH: usize = 4064;
+
+impl SessionKey {
+    /// Creates a session key that might exceed the size of a single cookie.
+    ///
+    /// The caller is responsible for making sure that it is either split across multiple cookies
+    /// or rejected when it is attached to the response.
+    pub(crate) fn new_unbounded(val: String) -> Self {
+        SessionKey(val)
+    }
+
+    /// Creates a session key reassembled from up to `max_chunks` cookies.
+    pub(crate) fn try_from_chunks(
+        val: String,
+        max_chunks: usize,
+    ) -> Result<Self, InvalidSessionKeyError> {
+        if val.len() > max_chunks * MAX_COOKIE_CONTENT_LENGTH {
+            return Err(anyhow::anyhow!(
+                "The session key is bigger than {} bytes, the upper limit on the content of {} \
+                cookies.",
+                max_chunks * MAX_COOKIE_CONTENT_LENGTH,
+                max_chunks
+            )
+            .into());
+        }
+
+        Ok(SessionKey(val))
+    }
+}
+
 impl TryFrom<String> for SessionKey {
     type Error = InvalidSessionKeyError;
 
     fn try_from(val: String) -> Result<Self, Self::Error> {
-        if val.len() > 4064 {
+        if val.len() > MAX_COOKIE_CONTENT_LENGTH {
             return Err(anyhow::anyhow!(
                 "The session key is bigger than 4064 bytes, the upper limit on cookie content."
             )
@@ -17,3 +17,53 @@ pub(crate) fn generate_session_key() -> SessionKey {
     // (i.e. length and character set)
     String::from_utf8(value).unwrap().try_into().unwrap()
 }
+
+/// Returns the subject the session belongs to, i.e. the value attached to `subject_key` in its
+/// state—if it is a string or a number.
+#[cfg(any(
+    feature = "in-memory-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session"
+))]
+pub(crate) fn session_subject(
+    session_state: &crate::storage::SessionState,
+    subject_key: &str,
+) -> Option<String> {
+    match session_state.get(subject_key)? {
+        serde_json::Value::String(subject) => Some(subject.clone()),
+        serde_json::Value::Number(subject) => Some(subject.to_string()),
+        _ => None,
+    }
+}
+
+/// Adds the session key `ARGV[1]` to the subject index `KEYS[1]`, a Redis set, and makes sure that
+/// the index outlives the session: its TTL is extended to `ARGV[2]` seconds if it would expire
+/// sooner. The TTL is never shortened, as other sessions in the index might live longer.
+///
+/// Without a TTL, the index of a subject who never comes back would never be cleaned up.
+#[cfg(any(feature = "redis-actor-session", feature = "redis-rs-session"))]
+pub(crate) const INDEX_SESSION_SCRIPT: &str = r#"
+redis.call('SADD', KEYS[1], ARGV[1])
+if redis.call('TTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+/// The error returned by [`IndexedSessionStore`] methods when the store was not told which key
+/// of the session state holds the subject.
+///
+/// [`IndexedSessionStore`]: crate::storage::IndexedSessionStore
+#[cfg(any(
+    feature = "in-memory-session",
+    feature = "redis-actor-session",
+    feature = "redis-rs-session",
+    feature = "sql-session"
+))]
+pub(crate) fn missing_subject_key() -> anyhow::Error {
+    anyhow::anyhow!(
+        "Sessions cannot be looked up by subject: the session state key holding the subject has \
+        not been configured"
+    )
+}
@@ -0,0 +1,266 @@
+//! Utilities to test request handlers relying on [`Session`].
+//!
+//! Handlers can be tested without [`SessionMiddleware`]: attach a [`TestSession`] to the request,
+//! then inspect the [`Session`] handle it returns once the request has been handled.
+//!
+//! ```
+//! use actix_web::{test, web, App, Responder};
+//! use actix_session::{test::TestSession, Session, SessionStatus};
+//!
+//! async fn visit(session: Session) -> actix_web::Result<impl Responder> {
+//!     let visits = session.get::<u32>("visits")?.unwrap_or_default() + 1;
+//!     session.insert("visits", visits)?;
+//!     Ok(visits.to_string())
+//! }
+//!
+//! # actix_web::rt::System::new().block_on(async {
+//! let app = test::init_service(App::new().route("/", web::get().to(visit))).await;
+//!
+//! let request = test::TestRequest::get().uri("/").to_request();
+//! let session = TestSession::new().insert("visits", 41).attach_to(&request);
+//!
+//! let body = test::call_and_read_body(&app, request).await;
+//! assert_eq!(body, "42");
+//! assert_eq!(session.status(), SessionStatus::Changed);
+//! assert_eq!(session.get::<u32>("visits").unwrap(), Some(42));
+//! # })
+//! ```
+//!
+//! To test handlers behind [`SessionMiddleware`], persist a [`TestSession`] in your storage
+//! backend via [`TestSession::save`] and attach the resulting [session cookie](session_cookie) to
+//! the request. Use [`read_session_cookie`] to get the session key out of the cookies set on the
+//! response.
+//!
+//! [`SessionMiddleware`]: crate::SessionMiddleware
+
+use actix_web::{
+    cookie::{Cookie, CookieJar, Key},
+    HttpMessage,
+};
+use serde::Serialize;
+
+use crate::{
+    config::{self, CookieContentSecurity},
+    storage::{SessionKey, SessionState, SessionStore},
+    Session, SessionLoadOutcome,
+};
+
+/// A session state to attach to test requests, or to persist in a storage backend.
+#[derive(Debug, Clone)]
+pub struct TestSession {
+    state: SessionState,
+    load_outcome: SessionLoadOutcome,
+}
+
+impl TestSession {
+    /// Create an empty session.
+    pub fn new() -> Self {
+        Self {
+            state: SessionState::new(),
+            load_outcome: SessionLoadOutcome::Loaded,
+        }
+    }
+
+    /// Add an entry to the session.
+    ///
+    /// # Panics
+    /// Panics if `value` cannot be serialized to JSON.
+    pub fn insert<T: Serialize>(mut self, key: impl Into<String>, value: T) -> Self {
+        let key = key.into();
+        let value = serde_json::to_value(value).unwrap_or_else(|err| {
+            panic!(
+                "Failed to serialize the value of the `{}` test session entry: {}",
+                key, err
+            )
+        });
+        self.state.insert(key, value);
+        self
+    }
+
+    /// Set the [outcome](Session::load_outcome) of the session load reported to handlers.
+    ///
+    /// Default is [`SessionLoadOutcome::Loaded`].
+    pub fn load_outcome(mut self, load_outcome: SessionLoadOutcome) -> Self {
+        self.load_outcome = load_outcome;
+        self
+    }
+
+    /// Returns the entries of the session.
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    /// Attach the session to `req`, as if [`SessionMiddleware`] had loaded it, and return a handle
+    /// to it.
+    ///
+    /// The handle shares its state with the [`Session`] extracted by handlers: use it to check
+    /// the [status](Session::status) and the [entries](Session::entries) of the session once the
+    /// request has been handled. `req` can be an `actix_http::Request` (built with
+    /// [`TestRequest::to_request`]), an [`HttpRequest`] or a [`ServiceRequest`].
+    ///
+    /// There must not be a [`SessionMiddleware`] in front of the handlers: it would replace the
+    /// attached session with the one loaded from the storage backend.
+    ///
+    /// [`SessionMiddleware`]: crate::SessionMiddleware
+    /// [`TestRequest::to_request`]: actix_web::test::TestRequest::to_request
+    /// [`HttpRequest`]: actix_web::HttpRequest
+    /// [`ServiceRequest`]: actix_web::dev::ServiceRequest
+    pub fn attach_to<R: HttpMessage>(&self, req: &R) -> Session {
+        self.attach_namespaced_to(req)
+    }
+
+    /// Attach the session to `req` as the session of [namespace](Session#namespaces) `N`, and
+    /// return a handle to it.
+    ///
+    /// See [`attach_to`](Self::attach_to) for more details.
+    pub fn attach_namespaced_to<N: 'static, R: HttpMessage>(&self, req: &R) -> Session<N> {
+        let mut extensions = req.extensions_mut();
+        Session::<N>::set_session(&mut extensions, self.state.clone());
+        Session::<N>::set_load_outcome(&mut extensions, self.load_outcome);
+        Session::get_session(&mut extensions)
+    }
+
+    /// Persist the session in `store`, with a time-to-live of one day, and return its session key.
+    ///
+    /// Turn the session key into a cookie using [`session_cookie`].
+    ///
+    /// # Panics
+    /// Panics if the session cannot be persisted.
+    pub async fn save<S: SessionStore>(&self, store: &S) -> SessionKey {
+        store
+            .save(self.state.clone(), &config::default_ttl())
+            .await
+            .unwrap_or_else(|err| panic!("Failed to persist the test session: {}", err))
+    }
+}
+
+impl Default for TestSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create a session cookie holding `session_key`, signed or encrypted using `key`—as
+/// [`SessionMiddleware`] would.
+///
+/// `name` and `content_security` must match the configuration of [`SessionMiddleware`]—`id` and
+/// [`CookieContentSecurity::Private`] by default.
+///
+/// ```
+/// use actix_web::{cookie::Key, test::TestRequest};
+/// use actix_session::{
+///     config::CookieContentSecurity,
+///     storage::InMemorySessionStore,
+///     test::{session_cookie, TestSession},
+/// };
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let key = Key::generate();
+/// let store = InMemorySessionStore::default();
+///
+/// let session_key = TestSession::new().insert("user_id", 42).save(&store).await;
+/// let request = TestRequest::get()
+///     .cookie(session_cookie("id", &session_key, &key, CookieContentSecurity::Private))
+///     .to_request();
+/// # })
+/// ```
+///
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+pub fn session_cookie(
+    name: &str,
+    session_key: &SessionKey,
+    key: &Key,
+    content_security: CookieContentSecurity,
+) -> Cookie<'static> {
+    let cookie = Cookie::new(name.to_owned(), session_key.as_ref().to_owned());
+
+    let mut jar = CookieJar::new();
+    match content_security {
+        CookieContentSecurity::Signed => jar.signed_mut(key).add(cookie),
+        CookieContentSecurity::Private => jar.private_mut(key).add(cookie),
+    }
+
+    jar.get(name)
+        .expect("The cookie has just been added to the jar")
+        .clone()
+}
+
+/// Extract the session key from a session cookie set by [`SessionMiddleware`], checking its
+/// signature (or decrypting it) using `key`.
+///
+/// It returns `None` if the cookie fails cryptographic checks, or if it is a removal cookie.
+///
+/// [`SessionMiddleware`]: crate::SessionMiddleware
+pub fn read_session_cookie(
+    cookie: &Cookie<'_>,
+    key: &Key,
+    content_security: CookieContentSecurity,
+) -> Option<SessionKey> {
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie.clone().into_owned());
+
+    let cookie = match content_security {
+        CookieContentSecurity::Signed => jar.signed(key).get(cookie.name()),
+        CookieContentSecurity::Private => jar.private(key).get(cookie.name()),
+    }?;
+
+    Some(cookie.value())
+        .filter(|value| !value.is_empty())
+        .map(|value| SessionKey::new_unbounded(value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as actix_test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::{config::CookieContentSecurity, SessionStatus};
+
+    async fn increment(session: Session) -> HttpResponse {
+        let counter = session.get::<i32>("counter").unwrap().unwrap_or_default();
+        session.insert("counter", counter + 1).unwrap();
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn attached_sessions_can_be_inspected_after_the_call() {
+        let app = actix_test::init_service(App::new().route("/", web::post().to(increment))).await;
+
+        let req = actix_test::TestRequest::post().uri("/").to_request();
+        let session = TestSession::new()
+            .insert("counter", 41)
+            .insert("user_id", "alice")
+            .load_outcome(SessionLoadOutcome::Expired)
+            .attach_to(&req);
+        assert_eq!(session.load_outcome(), SessionLoadOutcome::Expired);
+
+        let res = actix_test::call_service(&app, req).await;
+        assert!(res.status().is_success());
+        assert_eq!(session.status(), SessionStatus::Changed);
+        assert_eq!(session.get::<i32>("counter").unwrap(), Some(42));
+        assert_eq!(
+            session.get::<String>("user_id").unwrap().as_deref(),
+            Some("alice")
+        );
+    }
+
+    #[actix_web::test]
+    async fn session_cookies_round_trip() {
+        let key = Key::generate();
+        let session_key = SessionKey::new_unbounded("session-key".to_owned());
+
+        for policy in &[
+            CookieContentSecurity::Signed,
+            CookieContentSecurity::Private,
+        ] {
+            let cookie = session_cookie("id", &session_key, &key, *policy);
+            assert_eq!(cookie.name(), "id");
+            assert_ne!(cookie.value(), session_key.as_ref());
+
+            let read = read_session_cookie(&cookie, &key, *policy).unwrap();
+            assert_eq!(read.as_ref(), session_key.as_ref());
+
+            assert!(read_session_cookie(&cookie, &Key::generate(), *policy).is_none());
+        }
+    }
+}
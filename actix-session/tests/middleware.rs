@@ -1,8 +1,28 @@
-use actix_session::{storage::CookieSessionStore, Session, SessionMiddleware};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use actix_session::{
+    config::{
+        BrowserSession, ConflictPolicy, CookieContentSecurity, CookiePrefix,
+        FingerprintMismatchAction, FingerprintPolicy, HeaderTransport, TtlExtensionPolicy,
+    },
+    csrf::{CsrfMiddleware, CsrfToken},
+    events::{SessionEvent, SessionEventKind},
+    flash::{FlashMessages, Level},
+    storage::{
+        CookieSessionStore, InMemorySessionStore, IndexedSessionStore, LoadError, SaveError,
+        SessionKey, SessionState, SessionStore, UpdateError,
+    },
+    NamespacedSessionExt, Session, SessionExt, SessionMiddleware,
+};
 use actix_web::{
-    cookie::{time::Duration, Key},
-    test, web, App, Responder,
+    cookie::{time::Duration, Cookie, CookieJar, Key},
+    http::{header, StatusCode},
+    test, web, App, HttpRequest, Responder,
 };
+use serde::{Deserialize, Serialize};
 
 async fn login(session: Session) -> impl Responder {
     session.insert("user_id", "id").unwrap();
@@ -54,3 +74,1709 @@ async fn cookie_storage() -> std::io::Result<()> {
     assert_eq!(deletion_cookie.domain().unwrap(), "localhost");
     Ok(())
 }
+
+async fn fill_cart(session: Session, size: web::Path<usize>) -> impl Responder {
+    session.insert("cart", "x".repeat(*size)).unwrap();
+    "Cart updated"
+}
+
+async fn cart_size(session: Session) -> impl Responder {
+    let cart = session.get::<String>("cart").unwrap().unwrap_or_default();
+    cart.len().to_string()
+}
+
+#[actix_web::test]
+async fn oversized_cookie_storage_fails_without_chunking() {
+    let app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                Key::generate(),
+            ))
+            .route("/cart/{size}", web::post().to(fill_cart)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/cart/5000").to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[actix_web::test]
+async fn oversized_cookie_storage_is_split_across_cookies() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                    .cookie_max_chunks(3)
+                    .build(),
+            )
+            .route("/cart/{size}", web::post().to(fill_cart))
+            .route("/cart", web::get().to(cart_size))
+            .route("/logout", web::post().to(logout)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/cart/9000").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookies: Vec<_> = response.response().cookies().collect();
+    let names: Vec<_> = cookies.iter().map(|cookie| cookie.name()).collect();
+    assert_eq!(names, ["id", "id.1", "id.2"]);
+
+    // chunks are reassembled
+    let mut request = test::TestRequest::get().uri("/cart");
+    for cookie in &cookies {
+        request = request.cookie(cookie.clone());
+    }
+    let body = test::call_and_read_body(&app, request.to_request()).await;
+    assert_eq!(body, "9000");
+
+    // a missing chunk invalidates the session
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookies[0].clone())
+        .cookie(cookies[2].clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "0");
+
+    // chunks of different session keys cannot be mixed
+    let request = test::TestRequest::post().uri("/cart/9000").to_request();
+    let response = test::call_service(&app, request).await;
+    let other_cookies: Vec<_> = response.response().cookies().collect();
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookies[0].clone())
+        .cookie(other_cookies[1].clone())
+        .cookie(cookies[2].clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "0");
+
+    // a chunk cannot be passed off as a whole session key
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookies[0].clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "0");
+
+    // stale chunks are removed when the state shrinks
+    let mut request = test::TestRequest::post().uri("/cart/10");
+    for cookie in &cookies {
+        request = request.cookie(cookie.clone());
+    }
+    let response = test::call_service(&app, request.to_request()).await;
+    let shrunk: Vec<_> = response.response().cookies().collect();
+    assert_eq!(shrunk.len(), 3);
+    assert_eq!(shrunk[0].name(), "id");
+    assert_ne!(shrunk[0].max_age(), Some(Duration::ZERO));
+    for (cookie, name) in shrunk[1..].iter().zip(["id.1", "id.2"]) {
+        assert_eq!(cookie.name(), name);
+        assert_eq!(cookie.max_age(), Some(Duration::ZERO));
+    }
+
+    // all chunks are removed on logout
+    let mut request = test::TestRequest::post().uri("/logout");
+    for cookie in &cookies {
+        request = request.cookie(cookie.clone());
+    }
+    let response = test::call_service(&app, request.to_request()).await;
+    let removed: Vec<_> = response.response().cookies().collect();
+    assert_eq!(removed.len(), 3);
+    assert!(removed
+        .iter()
+        .all(|cookie| cookie.max_age() == Some(Duration::ZERO)));
+
+    // the cap on the number of chunks is enforced
+    let request = test::TestRequest::post().uri("/cart/20000").to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[actix_web::test]
+async fn cookies_signed_with_a_fallback_key_are_reissued() {
+    let old_key = Key::generate();
+    let new_key = Key::generate();
+
+    let old_app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                old_key.clone(),
+            ))
+            .route("/cart/{size}", web::post().to(fill_cart)),
+    )
+    .await;
+    let request = test::TestRequest::post().uri("/cart/10").to_request();
+    let response = test::call_service(&old_app, request).await;
+    let old_cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // without fallback keys, rotating the key logs everybody out
+    let app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                new_key.clone(),
+            ))
+            .route("/cart", web::get().to(cart_size)),
+    )
+    .await;
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(old_cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "0");
+
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), new_key.clone())
+                    .cookie_fallback_keys(vec![Key::generate(), old_key])
+                    .build(),
+            )
+            .route("/cart", web::get().to(cart_size)),
+    )
+    .await;
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(old_cookie)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let reissued_cookie = response.response().cookies().next().unwrap().into_owned();
+    assert_eq!(test::read_body(response).await, "10");
+
+    // the re-issued cookie is encrypted using the primary key
+    let app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                new_key,
+            ))
+            .route("/cart", web::get().to(cart_size)),
+    )
+    .await;
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(reissued_cookie)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!(test::read_body(response).await, "10");
+}
+
+fn fingerprinted_app_request(
+    uri: &str,
+    user_agent: &str,
+    peer_addr: &str,
+    cookie: Option<Cookie<'static>>,
+) -> test::TestRequest {
+    let mut request = test::TestRequest::post()
+        .uri(uri)
+        .insert_header((header::USER_AGENT, user_agent))
+        .peer_addr(peer_addr.parse().unwrap());
+    if let Some(cookie) = cookie {
+        request = request.cookie(cookie);
+    }
+    request
+}
+
+#[actix_web::test]
+async fn sessions_are_bound_to_the_client_fingerprint() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                    .fingerprint_policy(FingerprintPolicy::default().ip_prefix(24, 64))
+                    .build(),
+            )
+            .route("/cart/{size}", web::post().to(fill_cart))
+            .route("/cart", web::post().to(cart_size)),
+    )
+    .await;
+
+    let request = fingerprinted_app_request("/cart/10", "firefox", "10.0.0.1:80", None);
+    let response = test::call_service(&app, request.to_request()).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // same user agent, same network
+    let request =
+        fingerprinted_app_request("/cart", "firefox", "10.0.0.200:80", Some(cookie.clone()));
+    let response = test::call_service(&app, request.to_request()).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!(test::read_body(response).await, "10");
+
+    // the fingerprint survives changes to the session state
+    let request = fingerprinted_app_request("/cart/5", "firefox", "10.0.0.1:80", Some(cookie));
+    let response = test::call_service(&app, request.to_request()).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // different network
+    let request =
+        fingerprinted_app_request("/cart", "firefox", "10.0.1.1:80", Some(cookie.clone()));
+    let response = test::call_service(&app, request.to_request()).await;
+    let removal_cookie = response.response().cookies().next().unwrap();
+    assert_eq!(removal_cookie.max_age(), Some(Duration::ZERO));
+    assert_eq!(test::read_body(response).await, "0");
+
+    // different user agent
+    let request = fingerprinted_app_request("/cart", "curl", "10.0.0.1:80", Some(cookie));
+    let body = test::call_and_read_body(&app, request.to_request()).await;
+    assert_eq!(body, "0");
+}
+
+#[actix_web::test]
+async fn requests_with_a_mismatched_fingerprint_can_be_rejected() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                    .fingerprint_policy(
+                        FingerprintPolicy::default().on_mismatch(FingerprintMismatchAction::Reject),
+                    )
+                    .build(),
+            )
+            .route("/cart/{size}", web::post().to(fill_cart))
+            .route("/cart", web::post().to(cart_size)),
+    )
+    .await;
+
+    let request = fingerprinted_app_request("/cart/10", "firefox", "10.0.0.1:80", None);
+    let response = test::call_service(&app, request.to_request()).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    let request = fingerprinted_app_request("/cart", "curl", "10.0.0.1:80", Some(cookie.clone()));
+    let err = test::try_call_service(&app, request.to_request())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // the session is left untouched for its legitimate owner
+    let request = fingerprinted_app_request("/cart", "firefox", "192.168.0.1:80", Some(cookie));
+    let body = test::call_and_read_body(&app, request.to_request()).await;
+    assert_eq!(body, "10");
+}
+
+async fn renew(session: Session) -> impl Responder {
+    session.renew();
+    "Renewed"
+}
+
+#[actix_web::test]
+async fn session_lifecycle_events_are_dispatched_to_listeners() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let store = InMemorySessionStore::default();
+
+    let app = {
+        let events = Rc::clone(&events);
+        test::init_service(
+            App::new()
+                .wrap(
+                    SessionMiddleware::builder(store, Key::generate())
+                        .session_lifecycle(
+                            BrowserSession::default()
+                                .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                        )
+                        .event_listener(move |event: &SessionEvent<'_>| {
+                            events.borrow_mut().push((
+                                event.kind(),
+                                event.session_key().is_some(),
+                                event.request().uri.path().to_owned(),
+                                event.status(),
+                            ))
+                        })
+                        .build(),
+                )
+                .route("/login", web::post().to(login))
+                .route("/cart/{size}", web::post().to(fill_cart))
+                .route("/cart", web::get().to(cart_size))
+                .route("/renew", web::post().to(renew))
+                .route("/logout", web::post().to(logout)),
+        )
+        .await
+    };
+
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    for request in [
+        test::TestRequest::get().uri("/cart"),
+        test::TestRequest::post().uri("/cart/3"),
+    ] {
+        let request = request.cookie(cookie.clone()).to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.status().is_success());
+    }
+
+    let request = test::TestRequest::post()
+        .uri("/renew")
+        .cookie(cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let renewed_cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // the session key has been renewed, the previous one no longer matches any session state
+    let request = test::TestRequest::post()
+        .uri("/logout")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::post()
+        .uri("/logout")
+        .cookie(renewed_cookie)
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let mut tampered_cookie = cookie;
+    tampered_cookie.set_value("tampered");
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(tampered_cookie)
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let events = events.borrow();
+    let kinds: Vec<_> = events
+        .iter()
+        .map(|(kind, _, path, _)| (*kind, path.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (SessionEventKind::Created, "/login"),
+            (SessionEventKind::TtlExtended, "/cart"),
+            (SessionEventKind::Updated, "/cart/3"),
+            (SessionEventKind::Renewed, "/renew"),
+            (SessionEventKind::Expired, "/logout"),
+            (SessionEventKind::Purged, "/logout"),
+            (SessionEventKind::Invalid, "/cart"),
+        ]
+    );
+    assert!(events
+        .iter()
+        .all(|(_, _, _, status)| *status == StatusCode::OK));
+
+    // the session key cannot be extracted from a tampered session cookie
+    let with_session_key: Vec<_> = events.iter().map(|(_, key, ..)| *key).collect();
+    assert_eq!(
+        with_session_key,
+        [true, true, true, true, true, true, false]
+    );
+}
+
+/// A session store that fails to persist session state.
+#[derive(Default)]
+struct ReadOnlyStore(InMemorySessionStore);
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for ReadOnlyStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        self.0.load(session_key).await
+    }
+
+    async fn save(&self, _: SessionState, _: &Duration) -> Result<SessionKey, SaveError> {
+        Err(SaveError::Other(anyhow::anyhow!("The store is read-only")))
+    }
+
+    async fn update(
+        &self,
+        _: SessionKey,
+        _: SessionState,
+        _: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        Err(UpdateError::Other(anyhow::anyhow!(
+            "The store is read-only"
+        )))
+    }
+
+    async fn update_ttl(&self, _: &SessionKey, _: &Duration) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("The store is read-only"))
+    }
+
+    async fn delete(&self, _: &SessionKey) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("The store is read-only"))
+    }
+}
+
+#[actix_web::test]
+async fn session_events_are_dispatched_when_persisting_the_session_fails() {
+    let key = Key::generate();
+    let events = Rc::new(RefCell::new(Vec::new()));
+
+    let app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                InMemorySessionStore::default(),
+                key.clone(),
+            ))
+            .route("/login", web::post().to(login)),
+    )
+    .await;
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // the session key is unknown to the read-only store
+    let read_only_app = {
+        let events = Rc::clone(&events);
+        test::init_service(
+            App::new()
+                .wrap(
+                    SessionMiddleware::builder(ReadOnlyStore::default(), key)
+                        .event_listener(move |event: &SessionEvent<'_>| {
+                            events.borrow_mut().push((event.kind(), event.status()))
+                        })
+                        .build(),
+                )
+                .route("/login", web::post().to(login)),
+        )
+        .await
+    };
+    let request = test::TestRequest::post()
+        .uri("/login")
+        .cookie(cookie)
+        .to_request();
+    let err = test::try_call_service(&read_only_app, request)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+
+    assert_eq!(
+        *events.borrow(),
+        [(SessionEventKind::Expired, StatusCode::INTERNAL_SERVER_ERROR)]
+    );
+}
+
+/// A session store keeping track of how many times it was asked to load or touch session state.
+#[derive(Default)]
+struct CountingStore {
+    inner: InMemorySessionStore,
+    loads: Rc<Cell<usize>>,
+    ttl_updates: Rc<Cell<usize>>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for CountingStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        self.loads.set(self.loads.get() + 1);
+        self.inner.load(session_key).await
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        self.inner.save(session_state, ttl).await
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.inner.update(session_key, session_state, ttl).await
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.ttl_updates.set(self.ttl_updates.get() + 1);
+        self.inner.update_ttl(session_key, ttl).await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.inner.delete(session_key).await
+    }
+}
+
+async fn fill_cart_unloaded(req: HttpRequest) -> impl Responder {
+    req.get_session().insert("cart", "xx").unwrap();
+    "Cart updated"
+}
+
+async fn logout_unloaded(req: HttpRequest) -> impl Responder {
+    let session = req.get_session();
+    // the session state cannot be read before being loaded
+    assert!(session.get::<String>("user_id").is_err());
+    session.remove("user_id");
+
+    // the removal is replayed over the loaded state
+    session.load().await.unwrap();
+    assert_eq!(session.get::<String>("user_id").unwrap(), None);
+    assert!(session.get::<String>("cart").unwrap().is_some());
+    "Logged out"
+}
+
+async fn clear_unloaded(req: HttpRequest) -> impl Responder {
+    req.get_session().clear();
+    "Cleared"
+}
+
+async fn user_id(session: Session) -> impl Responder {
+    session
+        .get::<String>("user_id")
+        .unwrap()
+        .unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn session_state_is_loaded_lazily() {
+    let store = CountingStore::default();
+    let loads = Rc::clone(&store.loads);
+    let ttl_updates = Rc::clone(&store.ttl_updates);
+
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(store, Key::generate())
+                    .lazy_session_loading(true)
+                    .session_lifecycle(
+                        BrowserSession::default()
+                            .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                    )
+                    .build(),
+            )
+            .route("/login", web::post().to(login))
+            .route("/health", web::get().to(|| async { "OK" }))
+            .route("/cart", web::get().to(cart_size))
+            .route("/cart/{size}", web::post().to(fill_cart))
+            .route("/unloaded-cart", web::post().to(fill_cart_unloaded))
+            .route("/unloaded-logout", web::post().to(logout_unloaded))
+            .route("/unloaded-clear", web::post().to(clear_unloaded))
+            .route("/user_id", web::get().to(user_id)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // the session is never accessed: it is neither loaded nor persisted
+    let request = test::TestRequest::get()
+        .uri("/health")
+        .cookie(cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!((loads.get(), ttl_updates.get()), (0, 0));
+
+    let request = test::TestRequest::post()
+        .uri("/cart/3")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+    assert_eq!(loads.get(), 1);
+
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "3");
+    assert_eq!((loads.get(), ttl_updates.get()), (2, 1));
+
+    // sessions modified before being loaded are loaded before being persisted
+    let request = test::TestRequest::post()
+        .uri("/unloaded-cart")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+    assert_eq!(loads.get(), 3);
+
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "2");
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "id");
+
+    // removals made before the session is loaded are not undone by the loaded state
+    let request = test::TestRequest::post()
+        .uri("/unloaded-logout")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "");
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "2");
+
+    // and neither are clears
+    let request = test::TestRequest::post()
+        .uri("/unloaded-clear")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/cart")
+        .cookie(cookie)
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, "0");
+}
+
+async fn fill_cart_concurrently(
+    session: Session,
+    size: web::Path<usize>,
+    store: web::Data<InMemorySessionStore>,
+) -> impl Responder {
+    // a concurrent request adds a coupon to the session while this one is being handled
+    let session_key = store.sessions_for("id").await.unwrap().pop().unwrap();
+    let mut session_state = store.load(&session_key).await.unwrap().unwrap();
+    session_state.insert("coupon".to_owned(), "WELCOME".into());
+    store
+        .update(session_key, session_state, &Duration::minutes(10))
+        .await
+        .unwrap();
+
+    session.insert("cart", "x".repeat(*size)).unwrap();
+    "Cart updated"
+}
+
+async fn coupon(session: Session) -> impl Responder {
+    session.get::<String>("coupon").unwrap().unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn concurrent_updates_are_handled_according_to_the_conflict_policy() {
+    for (policy, expected_status, expected_cart, expected_coupon) in [
+        (ConflictPolicy::LastWriteWins, StatusCode::OK, "3", ""),
+        (ConflictPolicy::Reject, StatusCode::CONFLICT, "0", "WELCOME"),
+        (ConflictPolicy::RetryMerge, StatusCode::OK, "3", "WELCOME"),
+    ] {
+        let store = InMemorySessionStore::builder()
+            .subject_key("user_id")
+            .build();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(store.clone()))
+                .wrap(
+                    SessionMiddleware::builder(store, Key::generate())
+                        .conflict_policy(policy)
+                        .build(),
+                )
+                .route("/login", web::post().to(login))
+                .route("/cart", web::get().to(cart_size))
+                .route("/cart/{size}", web::post().to(fill_cart_concurrently))
+                .route("/coupon", web::get().to(coupon)),
+        )
+        .await;
+
+        let request = test::TestRequest::post().uri("/login").to_request();
+        let response = test::call_service(&app, request).await;
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+
+        let request = test::TestRequest::post()
+            .uri("/cart/3")
+            .cookie(cookie.clone())
+            .to_request();
+        let status = match test::try_call_service(&app, request).await {
+            Ok(response) => response.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        assert_eq!(status, expected_status, "{:?}", policy);
+
+        let request = test::TestRequest::get()
+            .uri("/cart")
+            .cookie(cookie.clone())
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, expected_cart, "{:?}", policy);
+
+        let request = test::TestRequest::get()
+            .uri("/coupon")
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, expected_coupon, "{:?}", policy);
+    }
+}
+
+async fn load_outcome(session: Session) -> impl Responder {
+    format!("{:?}", session.load_outcome())
+}
+
+#[actix_web::test]
+async fn the_load_outcome_is_exposed_to_request_handlers() {
+    for lazy_loading in [false, true] {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+                        .lazy_session_loading(lazy_loading)
+                        .build(),
+                )
+                .route("/login", web::post().to(login))
+                .route("/logout", web::post().to(logout))
+                .route("/outcome", web::get().to(load_outcome)),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/outcome").to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "Absent");
+
+        let request = test::TestRequest::post().uri("/login").to_request();
+        let response = test::call_service(&app, request).await;
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+
+        let request = test::TestRequest::get()
+            .uri("/outcome")
+            .cookie(cookie.clone())
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "Loaded");
+
+        let mut tampered_cookie = cookie.clone();
+        tampered_cookie.set_value("tampered");
+        let request = test::TestRequest::get()
+            .uri("/outcome")
+            .cookie(tampered_cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "Tampered");
+
+        let request = test::TestRequest::post()
+            .uri("/logout")
+            .cookie(cookie.clone())
+            .to_request();
+        test::call_service(&app, request).await;
+
+        let request = test::TestRequest::get()
+            .uri("/outcome")
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "Expired");
+    }
+}
+
+#[actix_web::test]
+async fn undecodable_session_states_are_reported_as_corrupted() {
+    let key = Key::generate();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .build(),
+            )
+            .route("/outcome", web::get().to(load_outcome)),
+    )
+    .await;
+
+    // a genuine session cookie, holding a session state that cannot be decoded
+    let mut jar = CookieJar::new();
+    jar.signed_mut(&key)
+        .add(Cookie::new("id", "not-a-session-state"));
+    let cookie = jar.get("id").unwrap().clone();
+
+    let request = test::TestRequest::get()
+        .uri("/outcome")
+        .cookie(cookie)
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "Corrupted");
+}
+
+/// Signs a session cookie holding `session_state`, as `CookieSessionStore` would.
+fn signed_session_cookie(key: &Key, session_state: serde_json::Value) -> Cookie<'static> {
+    let mut jar = CookieJar::new();
+    jar.signed_mut(key)
+        .add(Cookie::new("id", format!("~{}", session_state)));
+    jar.get("id").unwrap().clone()
+}
+
+#[actix_web::test]
+async fn timed_out_sessions_are_purged_before_reaching_handlers() {
+    let key = Key::generate();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .absolute_timeout(Duration::hours(8))
+                    .idle_timeout(Duration::minutes(30))
+                    .build(),
+            )
+            .route("/login", web::post().to(login))
+            .route("/user_id", web::get().to(user_id))
+            .route("/outcome", web::get().to(load_outcome))
+            .route(
+                "/keys",
+                web::get().to(|session: Session| async move {
+                    let mut keys: Vec<_> = session.entries().keys().cloned().collect();
+                    keys.sort();
+                    keys.join(",")
+                }),
+            ),
+    )
+    .await;
+
+    // the timestamps are recorded in the session state, but hidden from request handlers
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie.clone());
+    let recorded: serde_json::Value =
+        serde_json::from_str(&jar.signed(&key).get("id").unwrap().value()[1..]).unwrap();
+    assert!(recorded["$created_at"].is_i64());
+    assert!(recorded["$last_access"].is_i64());
+
+    let request = test::TestRequest::get()
+        .uri("/keys")
+        .cookie(cookie)
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "user_id");
+
+    let now = actix_web::cookie::time::OffsetDateTime::now_utc().unix_timestamp();
+    let active = signed_session_cookie(
+        &key,
+        serde_json::json!({
+            "user_id": "id",
+            "$created_at": now - 7 * 3600,
+            "$last_access": now - 60,
+        }),
+    );
+    let expired = signed_session_cookie(
+        &key,
+        serde_json::json!({
+            "user_id": "id",
+            "$created_at": now - 9 * 3600,
+            "$last_access": now - 60,
+        }),
+    );
+    let idle = signed_session_cookie(
+        &key,
+        serde_json::json!({
+            "user_id": "id",
+            "$created_at": now - 3600,
+            "$last_access": now - 31 * 60,
+        }),
+    );
+
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .cookie(active)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    // the time of last access has been refreshed
+    assert!(response.response().cookies().next().is_some());
+    assert_eq!(test::read_body(response).await, "id");
+
+    for cookie in [expired, idle] {
+        let request = test::TestRequest::get()
+            .uri("/user_id")
+            .cookie(cookie.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let removal_cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(removal_cookie.max_age(), Some(Duration::ZERO));
+        assert_eq!(test::read_body(response).await, "");
+
+        let request = test::TestRequest::get()
+            .uri("/outcome")
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "Expired");
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Undo {
+    item_id: u32,
+}
+
+async fn remove_item(session: Session) -> impl Responder {
+    session.flash(Level::Success, "Item removed").unwrap();
+    session.flash(Level::Info, Undo { item_id: 42 }).unwrap();
+    "Item removed"
+}
+
+async fn show_messages(messages: FlashMessages) -> impl Responder {
+    messages
+        .iter()
+        .map(|message| match message.text() {
+            Some(text) => format!("{}: {}", message.level(), text),
+            None => format!(
+                "{}: {:?}",
+                message.level(),
+                message.content_as::<Undo>().unwrap()
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[actix_web::test]
+async fn flash_messages_are_read_exactly_once() {
+    let app = test::init_service(
+        App::new()
+            .wrap(SessionMiddleware::new(
+                InMemorySessionStore::default(),
+                Key::generate(),
+            ))
+            .route("/login", web::post().to(login))
+            .route("/remove", web::post().to(remove_item))
+            .route("/messages", web::get().to(show_messages)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // reading messages when there are none leaves the session untouched
+    let request = test::TestRequest::get()
+        .uri("/messages")
+        .cookie(cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!(test::read_body(response).await, "");
+
+    let request = test::TestRequest::post()
+        .uri("/remove")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/messages")
+        .cookie(cookie.clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "success: Item removed\ninfo: Undo { item_id: 42 }");
+
+    let request = test::TestRequest::get()
+        .uri("/messages")
+        .cookie(cookie)
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "");
+}
+
+async fn flash_unloaded(req: HttpRequest) -> impl Responder {
+    req.get_session()
+        .flash(Level::Warning, "Cart almost full")
+        .unwrap();
+    "Flashed"
+}
+
+#[actix_web::test]
+async fn flash_messages_are_appended_to_unloaded_sessions() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+                    .lazy_session_loading(true)
+                    .build(),
+            )
+            .route("/remove", web::post().to(remove_item))
+            .route("/flash", web::post().to(flash_unloaded))
+            .route("/messages", web::get().to(show_messages)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/remove").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    let request = test::TestRequest::post()
+        .uri("/flash")
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/messages")
+        .cookie(cookie)
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(
+        body,
+        "success: Item removed\ninfo: Undo { item_id: 42 }\nwarning: Cart almost full"
+    );
+}
+
+async fn csrf_token(token: CsrfToken) -> impl Responder {
+    token.to_string()
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    content: String,
+}
+
+async fn post_comment(comment: web::Form<Comment>) -> impl Responder {
+    comment.into_inner().content
+}
+
+#[actix_web::test]
+async fn unsafe_requests_must_carry_the_session_csrf_token() {
+    let app = test::init_service(
+        App::new()
+            .wrap(CsrfMiddleware::new())
+            .wrap(SessionMiddleware::new(
+                InMemorySessionStore::default(),
+                Key::generate(),
+            ))
+            .route("/token", web::get().to(csrf_token))
+            .route("/comments", web::post().to(post_comment))
+            .route("/renew", web::post().to(renew)),
+    )
+    .await;
+
+    // safe methods go through without a token
+    let request = test::TestRequest::get().uri("/token").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+    let token = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(token.len(), 64);
+
+    // the token is stable across requests
+    let request = test::TestRequest::get()
+        .uri("/token")
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, request).await, token);
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .insert_header(("x-csrf-token", "not-the-token"))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    // a valid token from another session is rejected
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .insert_header(("x-csrf-token", token.as_str()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .insert_header(("x-csrf-token", token.as_str()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "Hello");
+
+    // the form body is still available to handlers once the token has been read from it
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .set_form([("content", "Hello"), ("csrf_token", token.as_str())])
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "Hello");
+
+    // renewing the session rotates the token
+    let request = test::TestRequest::post()
+        .uri("/renew")
+        .cookie(cookie)
+        .insert_header(("x-csrf-token", token.as_str()))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    let request = test::TestRequest::get()
+        .uri("/token")
+        .cookie(cookie)
+        .to_request();
+    let rotated = test::call_and_read_body(&app, request).await;
+    assert_eq!(rotated.len(), 64);
+    assert_ne!(rotated, token);
+}
+
+fn find_cookie(response: &actix_web::dev::ServiceResponse, name: &str) -> Option<Cookie<'static>> {
+    response
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == name)
+        .map(Cookie::into_owned)
+}
+
+#[actix_web::test]
+async fn double_submit_cookies_carry_a_session_bound_csrf_token() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                CsrfMiddleware::builder()
+                    .double_submit_cookie(Key::generate())
+                    .build(),
+            )
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                Key::generate(),
+            ))
+            .route("/token", web::get().to(csrf_token))
+            .route("/comments", web::post().to(post_comment))
+            .route("/renew", web::post().to(renew)),
+    )
+    .await;
+
+    let request = test::TestRequest::get().uri("/token").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = find_cookie(&response, "csrf_token").unwrap();
+    assert_ne!(cookie.http_only(), Some(true));
+    // the session holds the secret the token is bound to
+    let session_cookie = find_cookie(&response, "id").unwrap();
+    let token = test::read_body(response).await;
+    assert_eq!(token, cookie.value());
+
+    // the existing token is reused
+    let request = test::TestRequest::get()
+        .uri("/token")
+        .cookie(cookie.clone())
+        .cookie(session_cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!(test::read_body(response).await, cookie.value());
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .cookie(session_cookie.clone())
+        .insert_header(("x-csrf-token", cookie.value()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a cookie planted by another subdomain lacks a valid MAC
+    let planted = Cookie::new("csrf_token", "planted-token");
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(planted)
+        .cookie(session_cookie.clone())
+        .insert_header(("x-csrf-token", "planted-token"))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    // a valid token obtained by another client is not bound to the victim's session
+    let request = test::TestRequest::get().uri("/token").to_request();
+    let response = test::call_service(&app, request).await;
+    let attacker_cookie = find_cookie(&response, "csrf_token").unwrap();
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(attacker_cookie.clone())
+        .cookie(session_cookie.clone())
+        .insert_header(("x-csrf-token", attacker_cookie.value()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    // a token is rejected without a session
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .insert_header(("x-csrf-token", cookie.value()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .cookie(session_cookie.clone())
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    // renewing the session rotates the token
+    let request = test::TestRequest::post()
+        .uri("/renew")
+        .cookie(cookie.clone())
+        .cookie(session_cookie)
+        .insert_header(("x-csrf-token", cookie.value()))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let rotated = find_cookie(&response, "csrf_token").unwrap();
+    let session_cookie = find_cookie(&response, "id").unwrap();
+    assert_ne!(rotated.value(), cookie.value());
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(cookie.clone())
+        .cookie(session_cookie.clone())
+        .insert_header(("x-csrf-token", cookie.value()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let err = test::try_call_service(&app, request).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+    let request = test::TestRequest::post()
+        .uri("/comments")
+        .cookie(rotated.clone())
+        .cookie(session_cookie)
+        .insert_header(("x-csrf-token", rotated.value()))
+        .set_form([("content", "Hello")])
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn csrf_tokens_follow_the_namespace_of_the_middleware() {
+    struct Admin;
+
+    async fn admin_token(token: CsrfToken<Admin>) -> impl Responder {
+        token.to_string()
+    }
+
+    async fn admin_renew(admin_session: Session<Admin>) -> impl Responder {
+        admin_session.renew();
+        "Renewed"
+    }
+
+    let key = Key::generate();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                CsrfMiddleware::builder()
+                    .namespace::<Admin>()
+                    .double_submit_cookie(Key::generate())
+                    .build(),
+            )
+            .wrap(
+                SessionMiddleware::builder(InMemorySessionStore::default(), key.clone())
+                    .namespace::<Admin>()
+                    .cookie_name("admin-id".to_owned())
+                    .build(),
+            )
+            .wrap(SessionMiddleware::new(InMemorySessionStore::default(), key))
+            .route("/token", web::get().to(admin_token))
+            .route("/renew", web::post().to(admin_renew)),
+    )
+    .await;
+
+    let request = test::TestRequest::get().uri("/token").to_request();
+    let response = test::call_service(&app, request).await;
+    let cookie = find_cookie(&response, "csrf_token").unwrap();
+    // the secret is stored in the namespaced session
+    let admin_cookie = find_cookie(&response, "admin-id").unwrap();
+    assert!(find_cookie(&response, "id").is_none());
+
+    // renewing the namespaced session rotates the token
+    let request = test::TestRequest::post()
+        .uri("/renew")
+        .cookie(cookie.clone())
+        .cookie(admin_cookie)
+        .insert_header(("x-csrf-token", cookie.value()))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let rotated = find_cookie(&response, "csrf_token").unwrap();
+    assert_ne!(rotated.value(), cookie.value());
+}
+
+#[actix_web::test]
+async fn prefixed_and_partitioned_session_cookies() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+                    .cookie_prefix(Some(CookiePrefix::Host))
+                    .cookie_partitioned(true)
+                    .build(),
+            )
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    let set_cookie = response
+        .headers()
+        .get(header::SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(set_cookie.starts_with("__Host-id="));
+    assert!(set_cookie.contains("; Secure"));
+    assert!(set_cookie.contains("; Path=/"));
+    assert!(set_cookie.ends_with("; Partitioned"));
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+
+    // the removal cookie meets the same requirements, or browsers would ignore it
+    let request = test::TestRequest::post()
+        .uri("/logout")
+        .cookie(cookie)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let removal = response
+        .headers()
+        .get(header::SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(removal.starts_with("__Host-id=;"));
+    assert!(removal.contains("; Secure"));
+    assert!(removal.ends_with("; Partitioned"));
+}
+
+#[actix_web::test]
+#[should_panic(expected = "`__Host-` cookies must not have a domain")]
+async fn host_prefixed_session_cookies_cannot_have_a_domain() {
+    let _ = SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+        .cookie_name("__Host-session".to_owned())
+        .cookie_domain(Some("example.com".to_owned()))
+        .build();
+}
+
+#[actix_web::test]
+#[should_panic(expected = "`__Secure-` cookies must be secure")]
+async fn secure_prefixed_session_cookies_must_be_secure() {
+    let _ = SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+        .cookie_prefix(Some(CookiePrefix::Secure))
+        .cookie_secure(false)
+        .build();
+}
+
+#[actix_web::test]
+#[should_panic(expected = "partitioned cookies must be secure")]
+async fn partitioned_session_cookies_must_be_secure() {
+    let _ = SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+        .cookie_partitioned(true)
+        .cookie_secure(false)
+        .build();
+}
+
+#[actix_web::test]
+async fn invalid_session_cookie_configurations_are_reported_by_try_build() {
+    let err = SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+        .cookie_prefix(Some(CookiePrefix::Host))
+        .cookie_path("/admin".to_owned())
+        .try_build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Invalid session cookie configuration: `__Host-` cookies must have `/` as path"
+    );
+
+    assert!(
+        SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+            .cookie_prefix(Some(CookiePrefix::Host))
+            .try_build()
+            .is_ok()
+    );
+}
+
+#[actix_web::test]
+async fn session_keys_can_be_exchanged_via_headers() {
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(InMemorySessionStore::default(), Key::generate())
+                    .session_transport(
+                        HeaderTransport::default()
+                            .header_name(header::AUTHORIZATION)
+                            .scheme(Some("Session".to_owned()))
+                            .response_header_name(header::HeaderName::from_static("x-session-id")),
+                    )
+                    .build(),
+            )
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout))
+            .route("/user_id", web::get().to(user_id))
+            .route("/load_outcome", web::get().to(load_outcome)),
+    )
+    .await;
+
+    let request = test::TestRequest::post().uri("/login").to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    let session_key = response
+        .headers()
+        .get("x-session-id")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(!session_key.is_empty());
+
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .insert_header((header::AUTHORIZATION, format!("session {}", session_key)))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    // the session key is only returned when it changes
+    assert!(response.headers().get("x-session-id").is_none());
+    assert_eq!(test::read_body(response).await, "id");
+
+    // the session key is signed (or encrypted)
+    let request = test::TestRequest::get()
+        .uri("/load_outcome")
+        .insert_header((header::AUTHORIZATION, "Session forged-session-key"))
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "Tampered");
+
+    // credentials using other schemes are ignored
+    let request = test::TestRequest::get()
+        .uri("/load_outcome")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", session_key)))
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "Absent");
+
+    let request = test::TestRequest::post()
+        .uri("/logout")
+        .insert_header((header::AUTHORIZATION, format!("Session {}", session_key)))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.headers().get("x-session-id").unwrap(), "");
+
+    let request = test::TestRequest::get()
+        .uri("/load_outcome")
+        .insert_header((header::AUTHORIZATION, format!("Session {}", session_key)))
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "Expired");
+}
+
+#[actix_web::test]
+async fn outdated_session_states_are_migrated_before_reaching_handlers() {
+    let key = Key::generate();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    // version 0 -> 1: `user` is now stored as `user_id`
+                    .state_migration(|state| {
+                        if let Some(user) = state.remove("user") {
+                            state.insert("user_id".to_owned(), user);
+                        }
+                        Ok(())
+                    })
+                    // version 1 -> 2: user IDs must be strings
+                    .state_migration(|state| match state.get("user_id") {
+                        Some(user_id) if !user_id.is_string() => {
+                            anyhow::bail!("Unexpected user ID: {}", user_id)
+                        }
+                        _ => Ok(()),
+                    })
+                    .build(),
+            )
+            .route("/user_id", web::get().to(user_id))
+            .route("/outcome", web::get().to(load_outcome)),
+    )
+    .await;
+
+    let outdated = signed_session_cookie(&key, serde_json::json!({ "user": "id" }));
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .cookie(outdated)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+
+    // the migrated state is persisted, alongside its schema version
+    let cookie = response.response().cookies().next().unwrap().into_owned();
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie.clone());
+    let recorded: serde_json::Value =
+        serde_json::from_str(&jar.signed(&key).get("id").unwrap().value()[1..]).unwrap();
+    assert_eq!(recorded["$schema_version"], 2);
+    assert_eq!(recorded["user_id"], "id");
+    assert!(recorded.get("user").is_none());
+    assert_eq!(test::read_body(response).await, "id");
+
+    // up-to-date states are left untouched
+    let request = test::TestRequest::get()
+        .uri("/user_id")
+        .cookie(cookie)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert!(response.response().cookies().next().is_none());
+    assert_eq!(test::read_body(response).await, "id");
+
+    // states which cannot be migrated are reset
+    let unmigratable = signed_session_cookie(
+        &key,
+        serde_json::json!({ "user_id": 42, "$schema_version": 1 }),
+    );
+    let from_the_future = signed_session_cookie(
+        &key,
+        serde_json::json!({ "user_id": "id", "$schema_version": 3 }),
+    );
+    for cookie in [unmigratable, from_the_future] {
+        let request = test::TestRequest::get()
+            .uri("/outcome")
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let removal = response.response().cookies().next().unwrap();
+        assert_eq!(removal.value(), "");
+        assert_eq!(test::read_body(response).await, "Corrupted");
+    }
+}
+
+#[actix_web::test]
+async fn namespaced_sessions_are_managed_independently() {
+    struct Admin;
+
+    async fn admin_login(session: Session, admin_session: Session<Admin>) -> impl Responder {
+        admin_session.insert("admin_id", "root").unwrap();
+        admin_session.flash(Level::Info, "Welcome back").unwrap();
+        format!("{:?}", session.get::<String>("user_id").unwrap())
+    }
+
+    async fn admin_logout(session: Session, admin_session: Session<Admin>) -> impl Responder {
+        admin_session.purge();
+        format!("{:?}", session.get::<String>("user_id").unwrap())
+    }
+
+    async fn admin_id(req: HttpRequest) -> impl Responder {
+        let admin_session = req.get_namespaced_session::<Admin>();
+        format!("{:?}", admin_session.get::<String>("admin_id").unwrap())
+    }
+
+    async fn admin_messages(messages: FlashMessages<Admin>) -> impl Responder {
+        format!(
+            "{:?}",
+            messages.iter().map(|m| m.text()).collect::<Vec<_>>()
+        )
+    }
+
+    let key = Key::generate();
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/admin")
+                    .wrap(
+                        SessionMiddleware::builder(InMemorySessionStore::default(), key.clone())
+                            .namespace::<Admin>()
+                            .cookie_name("admin-id".to_owned())
+                            .cookie_path("/admin".to_owned())
+                            .session_lifecycle(BrowserSession::default())
+                            .build(),
+                    )
+                    .route("/login", web::post().to(admin_login))
+                    .route("/logout", web::post().to(admin_logout))
+                    .route("/id", web::get().to(admin_id))
+                    .route("/messages", web::get().to(admin_messages)),
+            )
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                key.clone(),
+            ))
+            .route("/login", web::post().to(login)),
+    )
+    .await;
+
+    let response =
+        test::call_service(&app, test::TestRequest::post().uri("/login").to_request()).await;
+    let user_cookie = response.response().cookies().next().unwrap().into_owned();
+    assert_eq!(user_cookie.name(), "id");
+
+    // both sessions are available to handlers, the default one is left untouched
+    let request = test::TestRequest::post()
+        .uri("/admin/login")
+        .cookie(user_cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let cookies = response.response().cookies().collect::<Vec<_>>();
+    assert_eq!(cookies.len(), 1);
+    let admin_cookie = cookies[0].clone().into_owned();
+    assert_eq!(admin_cookie.name(), "admin-id");
+    assert_eq!(admin_cookie.path(), Some("/admin"));
+    assert_eq!(test::read_body(response).await, "Some(\"id\")");
+
+    let request = test::TestRequest::get()
+        .uri("/admin/id")
+        .cookie(user_cookie.clone())
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "Some(\"root\")");
+
+    // flash messages are read from the session of the same namespace
+    let request = test::TestRequest::get()
+        .uri("/admin/messages")
+        .cookie(user_cookie.clone())
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "[Some(\"Welcome back\")]");
+
+    // purging the namespaced session does not affect the default one
+    let request = test::TestRequest::post()
+        .uri("/admin/logout")
+        .cookie(user_cookie.clone())
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    let cookies = response.response().cookies().collect::<Vec<_>>();
+    assert_eq!(cookies.len(), 1);
+    assert_eq!(cookies[0].name(), "admin-id");
+    assert_eq!(cookies[0].value(), "");
+    assert_eq!(test::read_body(response).await, "Some(\"id\")");
+
+    let request = test::TestRequest::get()
+        .uri("/admin/id")
+        .cookie(user_cookie)
+        .cookie(admin_cookie)
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, "None");
+}
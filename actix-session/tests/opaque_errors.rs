@@ -1,7 +1,8 @@
-use std::collections::HashMap;
 use std::convert::TryInto;
 
-use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_session::storage::{
+    LoadError, SaveError, SessionKey, SessionState, SessionStore, UpdateError,
+};
 use actix_session::{Session, SessionMiddleware};
 use actix_web::body::MessageBody;
 use actix_web::http::StatusCode;
@@ -45,10 +46,7 @@ struct MockStore;
 
 #[async_trait::async_trait(?Send)]
 impl SessionStore for MockStore {
-    async fn load(
-        &self,
-        _session_key: &SessionKey,
-    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+    async fn load(&self, _session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
         Err(LoadError::Other(anyhow::anyhow!(
             "My error full of implementation details"
         )))
@@ -56,7 +54,7 @@ impl SessionStore for MockStore {
 
     async fn save(
         &self,
-        _session_state: HashMap<String, String>,
+        _session_state: SessionState,
         _ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
         Ok("random_value".to_string().try_into().unwrap())
@@ -65,7 +63,7 @@ impl SessionStore for MockStore {
     async fn update(
         &self,
         _session_key: SessionKey,
-        _session_state: HashMap<String, String>,
+        _session_state: SessionState,
         _ttl: &Duration,
     ) -> Result<SessionKey, UpdateError> {
         todo!()
@@ -1,5 +1,7 @@
 use actix_session::{SessionExt, SessionStatus};
 use actix_web::{test, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 #[actix_web::test]
 async fn session() {
@@ -14,10 +16,7 @@ async fn session() {
 
     let res = req.into_response(HttpResponse::Ok().finish());
     let state: Vec<_> = res.get_session().entries().clone().into_iter().collect();
-    assert_eq!(
-        state.as_slice(),
-        [("key2".to_string(), "\"value2\"".to_string())]
-    );
+    assert_eq!(state.as_slice(), [("key2".to_string(), json!("value2"))]);
 }
 
 #[actix_web::test]
@@ -40,6 +39,34 @@ async fn get_session_from_request_head() {
     assert_eq!(res, Some(10));
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Cart {
+    items: Vec<String>,
+    total: u32,
+}
+
+#[actix_web::test]
+async fn structured_values_are_not_double_encoded() {
+    let session = test::TestRequest::default().to_srv_request().get_session();
+
+    let cart = Cart {
+        items: vec!["crab".to_owned()],
+        total: 42,
+    };
+    session.insert("cart", &cart).unwrap();
+    assert_eq!(
+        session.entries()["cart"],
+        json!({ "items": ["crab"], "total": 42 })
+    );
+    assert_eq!(session.get::<Cart>("cart").unwrap(), Some(cart));
+
+    assert!(session.get::<u32>("cart").is_err());
+    assert_eq!(
+        session.remove_as::<u32>("cart"),
+        Some(Err(json!({ "items": ["crab"], "total": 42 })))
+    );
+}
+
 #[actix_web::test]
 async fn purge_session() {
     let req = test::TestRequest::default().to_srv_request();
@@ -110,3 +137,18 @@ async fn clear_session_after_renew() {
     session.clear();
     assert_eq!(session.status(), SessionStatus::Renewed);
 }
+
+#[actix_web::test]
+async fn reserved_keys_cannot_be_inserted() {
+    let session = test::TestRequest::default().to_srv_request().get_session();
+
+    assert!(session.insert("$flash", "not a flash message").is_err());
+    assert!(session.insert("$created_at", 0).is_err());
+    assert!(session.entries().is_empty());
+    assert_eq!(session.status(), SessionStatus::Unchanged);
+
+    session.insert("price_in_$", 42).unwrap();
+    assert_eq!(session.get::<u32>("price_in_$").unwrap(), Some(42));
+    session.insert("$price", 42).unwrap();
+    assert_eq!(session.get::<u32>("$price").unwrap(), Some(42));
+}